@@ -0,0 +1,131 @@
+use crate::flow::{ELECTRON_COLOR, HOLE_COLOR};
+use crate::{BoardLayout, Side, Tile};
+use bevy::prelude::*;
+use rand::{Rng, rng};
+
+/// Majority carriers drawn on every P and N tile.
+const CARRIERS_PER_TILE: usize = 14;
+const CARRIER_SIZE: f32 = 0.06;
+/// Tiles per second.
+const CARRIER_SPEED: f32 = 0.25;
+/// How fast carriers are pushed out of a depletion zone that grew, in tiles per second.
+const CARRIER_SWEEP_SPEED: f32 = 0.6;
+
+/// A hole in a P tile or a free electron in an N tile, wandering around inside its tile.
+#[derive(Component)]
+pub struct Carrier {
+    /// In tile units from the top left corner of the tile, y grows downwards.
+    position: Vec2,
+    velocity: Vec2,
+}
+
+/// How far carriers stay away from each side of a P or N tile, as a share of the tile size.
+/// Next to a PN junction the carriers have recombined and left the depletion zone empty.
+#[derive(Component, Default, Clone, Copy, Debug)]
+pub struct DepletionZones {
+    left: f32,
+    right: f32,
+    top: f32,
+    bottom: f32,
+}
+
+impl DepletionZones {
+    pub fn side_mut(&mut self, side: Side) -> &mut f32 {
+        match side {
+            Side::Left => &mut self.left,
+            Side::Right => &mut self.right,
+            Side::Top => &mut self.top,
+            Side::Bottom => &mut self.bottom,
+        }
+    }
+}
+
+/// Adds the carriers of a P or N tile as children of the tile entity, does nothing for other tiles.
+pub fn spawn_carriers(tile_entity: &mut EntityCommands, tile: &Tile, tile_size: f32) {
+    let color = match tile {
+        Tile::P => HOLE_COLOR,
+        Tile::N => ELECTRON_COLOR,
+        _ => return,
+    };
+
+    let mut rng = rng();
+    tile_entity.insert(DepletionZones::default());
+    tile_entity.with_children(|parent| {
+        for _ in 0..CARRIERS_PER_TILE {
+            let position = Vec2::new(rng.random_range(0.1..0.9), rng.random_range(0.1..0.9));
+            let angle = rng.random_range(0.0..std::f32::consts::TAU);
+
+            parent.spawn((
+                Carrier {
+                    position,
+                    velocity: Vec2::from_angle(angle) * CARRIER_SPEED,
+                },
+                Sprite {
+                    color,
+                    custom_size: Some(Vec2::splat(CARRIER_SIZE * tile_size)),
+                    ..default()
+                },
+                Transform::from_xyz(position.x * tile_size, -position.y * tile_size, 0.5),
+            ));
+        }
+    });
+}
+
+/// Lets the carriers wander randomly and sweeps them out of the depletion zones of their tile.
+/// They are drawn at the current tile size, so they follow the board when the window is resized.
+pub fn move_carriers(
+    tiles: Query<(&DepletionZones, &Children)>,
+    mut carriers: Query<(&mut Carrier, &mut Transform, &mut Sprite)>,
+    layout: Res<BoardLayout>,
+    time: Res<Time>,
+) {
+    let mut rng = rng();
+    let tile_size = layout.tile_size();
+    let size = Some(Vec2::splat(CARRIER_SIZE * tile_size));
+    let delta = time.delta_secs();
+    let margin = CARRIER_SIZE / 2.0;
+
+    for (zones, children) in tiles.iter() {
+        // Allowed area in tile units, y grows downwards from the top left corner
+        let min = Vec2::new(zones.left.max(margin), zones.top.max(margin));
+        let max = Vec2::new(
+            1.0 - zones.right.max(margin),
+            1.0 - zones.bottom.max(margin),
+        );
+
+        for child in children.iter() {
+            let Ok((mut carrier, mut transform, mut sprite)) = carriers.get_mut(child) else {
+                continue;
+            };
+
+            let turn = rng.random_range(-3.0..3.0) * delta;
+            carrier.velocity = Vec2::from_angle(turn).rotate(carrier.velocity);
+
+            let mut position = carrier.position + carrier.velocity * delta;
+
+            if position.x < min.x {
+                carrier.velocity.x = carrier.velocity.x.abs();
+                position.x += (CARRIER_SWEEP_SPEED * delta).min(min.x - position.x);
+            }
+            if position.x > max.x {
+                carrier.velocity.x = -carrier.velocity.x.abs();
+                position.x -= (CARRIER_SWEEP_SPEED * delta).min(position.x - max.x);
+            }
+            if position.y < min.y {
+                carrier.velocity.y = carrier.velocity.y.abs();
+                position.y += (CARRIER_SWEEP_SPEED * delta).min(min.y - position.y);
+            }
+            if position.y > max.y {
+                carrier.velocity.y = -carrier.velocity.y.abs();
+                position.y -= (CARRIER_SWEEP_SPEED * delta).min(position.y - max.y);
+            }
+
+            carrier.position = position;
+            transform.translation.x = position.x * tile_size;
+            transform.translation.y = -position.y * tile_size;
+            if sprite.custom_size != size {
+                sprite.custom_size = size;
+            }
+        }
+    }
+}
@@ -0,0 +1,1114 @@
+//! DC nodal analysis of the board.
+//!
+//! Every edge between two cells (and every edge on the border of the board) is a node of the
+//! circuit. Tiles connect the edges on their sides with elements: cables, lamps and resistor tiles
+//! are ohmic resistors, junction tiles join their sides in a node in their centre, the battery is a
+//! voltage source with a small internal resistance, and P and N tiles are bulk semiconductor with a
+//! node in their centre. Wherever a P tile touches an N tile the two form a PN junction, modelled
+//! as a piecewise-linear diode, and so is an LED, which also glows while it conducts. A P or N tile
+//! with junctions on two opposite sides is the base of a transistor (NPN or PNP): whichever of its
+//! junctions conducts acts as the emitter and drives most of its current through the other
+//! junction, so a small base current switches the collector-emitter path. Two tiles are only
+//! connected if both have something on their shared edge, so no explicit adjacency rules are
+//! needed.
+//!
+//! Any number of batteries can be on the board. Their voltages add up in series, cancel out when
+//! wired against each other and share the load in parallel, and a lamp only glows once the voltage
+//! across it reaches `LAMP_MIN_VOLTS`.
+//!
+//! Capacitors make the circuit change over time. [`analyze`] gives the steady state, in which every
+//! capacitor is charged and blocks the current. [`simulate`] gives one time step instead: a
+//! capacitor then acts like a battery with the voltage of its charge and a small internal
+//! resistance, and [`Analysis::charge_after`] moves its charge on by the current through it.
+
+use crate::{Grid, Side, Tile};
+use std::collections::HashMap;
+
+const BATTERY_VOLTS: f64 = 4.5;
+const BATTERY_RESISTANCE: f64 = 0.5;
+const LAMP_RESISTANCE: f64 = 10.0;
+const CABLE_RESISTANCE: f64 = 0.01;
+const BULK_RESISTANCE: f64 = 0.5;
+
+/// The junction conducts once this voltage is exceeded and then behaves like a small resistor.
+const DIODE_FORWARD_VOLTS: f64 = 0.7;
+const DIODE_ON_RESISTANCE: f64 = 0.1;
+const DIODE_OFF_CONDUCTANCE: f64 = 1e-9;
+const MAX_DIODE_ITERATIONS: usize = 32;
+
+/// Share of the emitter current that crosses the base to the collector, a current gain of 99.
+const TRANSISTOR_ALPHA: f64 = 0.99;
+
+/// Charging a capacitor through a lamp takes a few seconds, so does discharging it. The internal
+/// resistance keeps the charging current of a capacitor wired straight to a battery below
+/// `SHORT_CIRCUIT_CURRENT`.
+const CAPACITOR_FARADS: f64 = 0.2;
+const CAPACITOR_RESISTANCE: f64 = 2.0;
+
+/// LEDs need a higher voltage than a plain junction. The on resistance includes the series resistor
+/// a real LED would need, so it survives being wired straight to a battery.
+const LED_FORWARD_VOLTS: f64 = 2.0;
+const LED_ON_RESISTANCE: f64 = 10.0;
+
+/// Tiny conductance from every node to ground, so parts that are not connected to anything still
+/// have a defined voltage.
+const GMIN: f64 = 1e-9;
+
+/// A fully forward biased junction still shows this much of its depletion layer.
+const MIN_DEPLETION_WIDTH: f64 = 0.2;
+
+/// Parts carrying less current than this are left out of [`Analysis::current_path`].
+const PATH_MIN_CURRENT: f64 = 1e-3;
+
+/// Voltage a lamp needs to glow. A single battery lights two lamps in series, but not once a
+/// junction or a third lamp is added to their loop.
+const LAMP_MIN_VOLTS: f64 = 2.0;
+/// Current through a lamp connected straight to one battery, which is brightness `1.0`.
+const LAMP_NOMINAL_CURRENT: f64 = BATTERY_VOLTS / (LAMP_RESISTANCE + BATTERY_RESISTANCE);
+
+/// Battery current from which on the board counts as short-circuited. Even four lamps in parallel
+/// on one battery stay well below it.
+const SHORT_CIRCUIT_CURRENT: f64 = 3.0;
+
+/// Forward current an LED needs to glow.
+const LED_MIN_CURRENT: f64 = 0.02;
+/// Current through an LED connected straight to one battery, which is brightness `1.0`.
+const LED_NOMINAL_CURRENT: f64 =
+    (BATTERY_VOLTS - LED_FORWARD_VOLTS) / (LED_ON_RESISTANCE + BATTERY_RESISTANCE);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Element {
+    Cable,
+    Lamp,
+    /// Resistor with the given value in ohms.
+    Resistor(u32),
+    /// `a` is the plus pole, `b` the minus pole.
+    Battery,
+    /// P or N material between the centre of its tile and one side.
+    Bulk,
+    /// PN junction on the given side of the P tile, `a` is the P side and `b` the N side.
+    Junction(Side),
+    /// Light-emitting diode, `a` is the anode and `b` the cathode.
+    Led,
+    /// Capacitor, charged when `a` has a higher voltage than `b`.
+    Capacitor,
+}
+
+impl Element {
+    /// Forward voltage and on resistance of the diodes, `None` for every other element.
+    fn diode(&self) -> Option<(f64, f64)> {
+        match self {
+            Element::Junction(_) => Some((DIODE_FORWARD_VOLTS, DIODE_ON_RESISTANCE)),
+            Element::Led => Some((LED_FORWARD_VOLTS, LED_ON_RESISTANCE)),
+            _ => None,
+        }
+    }
+}
+
+/// Voltage from entry to exit of every capacitor on the board, by its cell.
+pub type Charges = HashMap<(usize, usize), f64>;
+
+/// A point on the board: the middle of a side of `cell`, or the centre of `cell` if `side` is
+/// `None`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PathPoint {
+    pub cell: (usize, usize),
+    pub side: Option<Side>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Part {
+    pub cell: (usize, usize),
+    pub element: Element,
+    pub a: usize,
+    pub b: usize,
+}
+
+/// Result of [`analyze`] or [`simulate`]: node voltages and the current through every part.
+#[derive(Clone, Debug)]
+pub struct Analysis {
+    nodes: Nodes,
+    parts: Vec<Part>,
+    /// Charges the capacitors had at the start of the time step, `None` for the steady state.
+    charges: Option<Charges>,
+    voltages: Vec<f64>,
+    /// Current from `a` to `b` through the part with the same index, in amperes. For batteries it
+    /// is the current leaving the plus pole.
+    currents: Vec<f64>,
+}
+
+impl Analysis {
+    /// Current through the lamp at `cell`, zero if there is no lamp.
+    pub fn lamp_current(&self, cell: (usize, usize)) -> f64 {
+        self.parts
+            .iter()
+            .zip(self.currents.iter())
+            .filter(|(part, _)| part.cell == cell && part.element == Element::Lamp)
+            .map(|(_, current)| current.abs())
+            .sum()
+    }
+
+    /// Voltage across the lamp at `cell`, zero if there is no lamp.
+    pub fn lamp_volts(&self, cell: (usize, usize)) -> f64 {
+        self.parts
+            .iter()
+            .filter(|part| part.cell == cell && part.element == Element::Lamp)
+            .map(|part| self.voltage(part).abs())
+            .fold(0.0, f64::max)
+    }
+
+    /// Current from anode to cathode through the LED at `cell`, zero if there is no LED.
+    pub fn led_current(&self, cell: (usize, usize)) -> f64 {
+        self.parts
+            .iter()
+            .zip(self.currents.iter())
+            .filter(|(part, _)| part.cell == cell && part.element == Element::Led)
+            .map(|(_, current)| *current)
+            .sum()
+    }
+
+    /// Voltage of the capacitor at `cell` after the current of this time step charged or discharged
+    /// it for `seconds`. Zero if there is no capacitor.
+    pub fn charge_after(&self, cell: (usize, usize), seconds: f64) -> f64 {
+        let current: f64 = self
+            .parts
+            .iter()
+            .zip(self.currents.iter())
+            .filter(|(part, _)| part.cell == cell && part.element == Element::Capacitor)
+            .map(|(_, current)| *current)
+            .sum();
+        charge(self.charges.as_ref(), cell) + current * seconds / CAPACITOR_FARADS
+    }
+
+    /// Whether a battery drives a huge current because its poles are connected without a load.
+    pub fn is_short_circuit(&self) -> bool {
+        self.parts
+            .iter()
+            .zip(self.currents.iter())
+            .any(|(part, current)| {
+                part.element == Element::Battery && current.abs() >= SHORT_CIRCUIT_CURRENT
+            })
+    }
+
+    /// Whether the lamp or LED at `cell` glows.
+    pub fn is_lit(&self, cell: (usize, usize)) -> bool {
+        self.lamp_volts(cell) >= LAMP_MIN_VOLTS || self.led_current(cell) >= LED_MIN_CURRENT
+    }
+
+    /// Power of the lamp at `cell` relative to a lamp on its own battery, or current of the LED
+    /// relative to an LED on its own battery. `0.0` if it is dark.
+    pub fn brightness(&self, cell: (usize, usize)) -> f32 {
+        if !self.is_lit(cell) {
+            return 0.0;
+        }
+        let lamp = (self.lamp_current(cell) / LAMP_NOMINAL_CURRENT).powi(2);
+        let led = self.led_current(cell) / LED_NOMINAL_CURRENT;
+        lamp.max(led) as f32
+    }
+
+    /// Width of the depletion layer of the junction between the P tile at `cell` and the N tile on
+    /// `side`, relative to the width without any voltage. `None` if there is no such junction.
+    ///
+    /// The layer grows with the square root of the reverse voltage and shrinks under forward bias
+    /// until the junction conducts.
+    pub fn depletion_width(&self, cell: (usize, usize), side: Side) -> Option<f64> {
+        let junction = self
+            .parts
+            .iter()
+            .find(|part| part.cell == cell && part.element == Element::Junction(side))?;
+        let bias = (DIODE_FORWARD_VOLTS - self.voltage(junction)) / DIODE_FORWARD_VOLTS;
+        Some(bias.max(0.0).sqrt().max(MIN_DEPLETION_WIDTH))
+    }
+
+    /// The loop the current takes from the plus pole of the strongest battery back to it, in the
+    /// direction of the conventional current. Where the current splits, the path follows the
+    /// larger branch. Empty if no current flows.
+    pub fn current_path(&self) -> Vec<PathPoint> {
+        // Start and end node of every part in the direction the current flows through it
+        let flow = |index: usize| {
+            let (part, current) = (&self.parts[index], self.currents[index]);
+            let forward = match part.element {
+                Element::Battery => current < 0.0,
+                _ => current >= 0.0,
+            };
+            match forward {
+                true => (part.a, part.b),
+                false => (part.b, part.a),
+            }
+        };
+        let carries_current = |index: usize| self.currents[index].abs() >= PATH_MIN_CURRENT;
+
+        let Some(battery) = (0..self.parts.len())
+            .filter(|&index| self.parts[index].element == Element::Battery)
+            .filter(|&index| carries_current(index))
+            .max_by(|&a, &b| self.currents[a].abs().total_cmp(&self.currents[b].abs()))
+        else {
+            return Vec::new();
+        };
+
+        let (end, start) = flow(battery);
+        let mut used = vec![false; self.parts.len()];
+        used[battery] = true;
+        let mut path = vec![self.point(battery, start)];
+        let mut node = start;
+
+        while node != end {
+            let Some(next) = (0..self.parts.len())
+                .filter(|&index| !used[index] && carries_current(index) && flow(index).0 == node)
+                .max_by(|&a, &b| self.currents[a].abs().total_cmp(&self.currents[b].abs()))
+            else {
+                return Vec::new();
+            };
+            used[next] = true;
+            self.extend_path(&mut path, next, flow(next));
+            node = flow(next).1;
+        }
+
+        self.extend_path(&mut path, battery, (end, start));
+        path
+    }
+
+    /// Adds the way through the part from node `from` to node `to`, without the starting point.
+    fn extend_path(&self, path: &mut Vec<PathPoint>, index: usize, (from, to): (usize, usize)) {
+        let (start, end) = (self.point(index, from), self.point(index, to));
+
+        // Go through the centre of the tile instead of cutting its corner
+        if start.side.is_some() && end.side.is_some() {
+            path.push(PathPoint {
+                cell: self.parts[index].cell,
+                side: None,
+            });
+        }
+        path.push(end);
+    }
+
+    /// Where the node at one end of the part lies on the part's tile.
+    fn point(&self, index: usize, node: usize) -> PathPoint {
+        let cell = self.parts[index].cell;
+        let side = [Side::Left, Side::Right, Side::Top, Side::Bottom]
+            .into_iter()
+            .find(|&side| self.nodes.edge(cell, side) == node);
+        PathPoint { cell, side }
+    }
+
+    /// Voltage from `a` to `b` of the part.
+    pub fn voltage(&self, part: &Part) -> f64 {
+        self.voltages[part.a] - self.voltages[part.b]
+    }
+}
+
+/// Numbers the edges of the board and the extra nodes inside tiles.
+#[derive(Clone, Debug)]
+struct Nodes {
+    width: usize,
+    height: usize,
+    count: usize,
+}
+
+impl Nodes {
+    fn new(grid: &Grid) -> Nodes {
+        let (width, height) = (grid.width(), grid.height());
+        Nodes {
+            width,
+            height,
+            count: (width + 1) * height + width * (height + 1),
+        }
+    }
+
+    /// Node on the given side of the cell, shared with the neighbouring cell on that side.
+    fn edge(&self, (x, y): (usize, usize), side: Side) -> usize {
+        let vertical = |edge_x: usize| y * (self.width + 1) + edge_x;
+        let horizontal = |edge_y: usize| (self.width + 1) * self.height + edge_y * self.width + x;
+        match side {
+            Side::Left => vertical(x),
+            Side::Right => vertical(x + 1),
+            Side::Top => horizontal(y),
+            Side::Bottom => horizontal(y + 1),
+        }
+    }
+
+    fn internal(&mut self) -> usize {
+        self.count += 1;
+        self.count - 1
+    }
+}
+
+/// Turns the board into parts, the pairs of junction parts that form transistors and the number of
+/// nodes.
+fn build(grid: &Grid) -> (Vec<Part>, Vec<(usize, usize)>, usize) {
+    let mut nodes = Nodes::new(grid);
+    let mut parts = Vec::new();
+
+    for y in 0..grid.height() {
+        for x in 0..grid.width() {
+            let cell = (x, y);
+            let Some(tile) = grid.get(x, y).unwrap() else {
+                continue;
+            };
+
+            let mut add = |element, a, b| {
+                parts.push(Part {
+                    cell,
+                    element,
+                    a,
+                    b,
+                })
+            };
+
+            match *tile {
+                Tile::Cable { entry, exit } => {
+                    add(
+                        Element::Cable,
+                        nodes.edge(cell, entry),
+                        nodes.edge(cell, exit),
+                    );
+                }
+                Tile::Switch {
+                    entry,
+                    exit,
+                    closed: true,
+                } => {
+                    add(
+                        Element::Cable,
+                        nodes.edge(cell, entry),
+                        nodes.edge(cell, exit),
+                    );
+                }
+                // An open switch connects nothing
+                Tile::Switch { closed: false, .. } => {}
+                Tile::TJunction { .. } | Tile::Cross => {
+                    let center = nodes.internal();
+                    for side in [Side::Left, Side::Right, Side::Top, Side::Bottom] {
+                        if !matches!(*tile, Tile::TJunction { missing } if missing == side) {
+                            add(Element::Cable, center, nodes.edge(cell, side));
+                        }
+                    }
+                }
+                Tile::Crossover => {
+                    add(
+                        Element::Cable,
+                        nodes.edge(cell, Side::Left),
+                        nodes.edge(cell, Side::Right),
+                    );
+                    add(
+                        Element::Cable,
+                        nodes.edge(cell, Side::Top),
+                        nodes.edge(cell, Side::Bottom),
+                    );
+                }
+                Tile::Resistor { entry, exit, ohms } => {
+                    add(
+                        Element::Resistor(ohms),
+                        nodes.edge(cell, entry),
+                        nodes.edge(cell, exit),
+                    );
+                }
+                Tile::Led { anode, cathode, .. } => {
+                    add(
+                        Element::Led,
+                        nodes.edge(cell, anode),
+                        nodes.edge(cell, cathode),
+                    );
+                }
+                Tile::Capacitor { entry, exit } => {
+                    add(
+                        Element::Capacitor,
+                        nodes.edge(cell, entry),
+                        nodes.edge(cell, exit),
+                    );
+                }
+                Tile::Lamp { entry, exit, .. } => {
+                    add(
+                        Element::Lamp,
+                        nodes.edge(cell, entry),
+                        nodes.edge(cell, exit),
+                    );
+                }
+                Tile::Battery {
+                    plus_side,
+                    minus_side,
+                } => {
+                    add(
+                        Element::Battery,
+                        nodes.edge(cell, plus_side),
+                        nodes.edge(cell, minus_side),
+                    );
+                }
+                Tile::P | Tile::N => {
+                    let center = nodes.internal();
+                    for side in [Side::Left, Side::Right, Side::Top, Side::Bottom] {
+                        let edge = nodes.edge(cell, side);
+                        let neighbour = grid.neighbour(cell, side);
+
+                        // The junction belongs to the P tile, the N tile only adds its bulk
+                        match (tile, neighbour) {
+                            (Tile::P, Some(Tile::N)) => add(Element::Junction(side), center, edge),
+                            _ => add(Element::Bulk, center, edge),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Junctions on opposite sides of a tile are the two junctions of a transistor
+    let junctions: HashMap<usize, usize> = parts
+        .iter()
+        .enumerate()
+        .filter(|(_, part)| matches!(part.element, Element::Junction(_)))
+        .map(|(index, part)| (part.b, index))
+        .collect();
+    let mut transistors = Vec::new();
+    for y in 0..grid.height() {
+        for x in 0..grid.width() {
+            if !matches!(grid.get(x, y).unwrap(), Some(Tile::P | Tile::N)) {
+                continue;
+            }
+            for (first, second) in [(Side::Left, Side::Right), (Side::Top, Side::Bottom)] {
+                if let (Some(&first), Some(&second)) = (
+                    junctions.get(&nodes.edge((x, y), first)),
+                    junctions.get(&nodes.edge((x, y), second)),
+                ) {
+                    transistors.push((first, second));
+                }
+            }
+        }
+    }
+
+    (parts, transistors, nodes.count)
+}
+
+/// Computes the steady state of the circuit on the board, with every capacitor fully charged.
+pub fn analyze(grid: &Grid) -> Analysis {
+    solve(grid, None)
+}
+
+/// Computes the currents right now, while the capacitors hold the given charges. Capacitors that
+/// are missing from `charges` are empty.
+pub fn simulate(grid: &Grid, charges: &Charges) -> Analysis {
+    solve(grid, Some(charges))
+}
+
+fn solve(grid: &Grid, charges: Option<&Charges>) -> Analysis {
+    let (parts, transistors, node_count) = build(grid);
+
+    // Without a closed loop through a battery or a charged capacitor no current flows anywhere
+    let charged = charges.is_some_and(|charges| charges.values().any(|&volts| volts != 0.0));
+    if !charged && !has_closed_loop(&parts, node_count, charges.is_some()) {
+        return Analysis {
+            nodes: Nodes::new(grid),
+            charges: charges.cloned(),
+            currents: vec![0.0; parts.len()],
+            voltages: vec![0.0; node_count],
+            parts,
+        };
+    }
+
+    let mut conducting = vec![false; parts.len()];
+    let mut voltages = vec![0.0; node_count];
+
+    for _ in 0..MAX_DIODE_ITERATIONS {
+        voltages = solve_nodes(&parts, &transistors, &conducting, charges, node_count);
+
+        // Flip every diode whose assumed state contradicts the solution
+        let mut changed = false;
+        for (index, part) in parts.iter().enumerate() {
+            let Some((forward_volts, _)) = part.element.diode() else {
+                continue;
+            };
+            let volts = voltages[part.a] - voltages[part.b];
+            let should_conduct = match conducting[index] {
+                true => volts >= forward_volts,
+                false => volts > forward_volts,
+            };
+            if should_conduct != conducting[index] {
+                conducting[index] = should_conduct;
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    let mut currents: Vec<f64> = parts
+        .iter()
+        .zip(conducting.iter())
+        .map(|(part, &on)| {
+            let volts = voltages[part.a] - voltages[part.b];
+            part_current(part, on, charges, volts)
+        })
+        .collect();
+
+    // The collector current flows backwards through the collector junction
+    for (emitter, collector) in transistor_paths(&transistors) {
+        if conducting[emitter] {
+            currents[collector] -= TRANSISTOR_ALPHA * currents[emitter];
+        }
+    }
+
+    Analysis {
+        nodes: Nodes::new(grid),
+        parts,
+        charges: charges.cloned(),
+        voltages,
+        currents,
+    }
+}
+
+/// A zero ohm resistor is treated as a cable, which keeps the conductance finite.
+fn resistance(ohms: u32) -> f64 {
+    f64::from(ohms).max(CABLE_RESISTANCE)
+}
+
+/// Voltage of the capacitor at `cell`, zero if it is empty.
+fn charge(charges: Option<&Charges>, cell: (usize, usize)) -> f64 {
+    charges
+        .and_then(|charges| charges.get(&cell))
+        .copied()
+        .unwrap_or(0.0)
+}
+
+fn part_current(part: &Part, conducting: bool, charges: Option<&Charges>, volts: f64) -> f64 {
+    match part.element {
+        Element::Cable => volts / CABLE_RESISTANCE,
+        Element::Lamp => volts / LAMP_RESISTANCE,
+        Element::Resistor(ohms) => volts / resistance(ohms),
+        Element::Bulk => volts / BULK_RESISTANCE,
+        // Current leaving the plus pole
+        Element::Battery => (BATTERY_VOLTS - volts) / BATTERY_RESISTANCE,
+        Element::Junction(_) | Element::Led => {
+            let (forward_volts, on_resistance) = part.element.diode().unwrap();
+            match conducting {
+                true => (volts - forward_volts) / on_resistance,
+                false => volts * DIODE_OFF_CONDUCTANCE,
+            }
+        }
+        // A charged capacitor lets no current through in the steady state
+        Element::Capacitor => match charges {
+            Some(_) => (volts - charge(charges, part.cell)) / CAPACITOR_RESISTANCE,
+            None => 0.0,
+        },
+    }
+}
+
+/// Whether some battery's poles are connected to each other through other parts, which may include
+/// other batteries. Capacitors only connect anything while they are `charging`, in the steady
+/// state they block.
+fn has_closed_loop(parts: &[Part], node_count: usize, charging: bool) -> bool {
+    fn find(roots: &mut [usize], mut node: usize) -> usize {
+        while roots[node] != node {
+            roots[node] = roots[roots[node]];
+            node = roots[node];
+        }
+        node
+    }
+
+    let connects = |part: &Part| match part.element {
+        Element::Capacitor => charging,
+        _ => true,
+    };
+    parts
+        .iter()
+        .enumerate()
+        .filter(|(_, part)| part.element == Element::Battery)
+        .any(|(battery, part)| {
+            let mut roots: Vec<usize> = (0..node_count).collect();
+            for (index, other) in parts.iter().enumerate() {
+                if index != battery && connects(other) {
+                    let (a, b) = (find(&mut roots, other.a), find(&mut roots, other.b));
+                    roots[a] = b;
+                }
+            }
+            find(&mut roots, part.a) == find(&mut roots, part.b)
+        })
+}
+
+/// Both ways a transistor can be used, as (emitter junction, collector junction).
+fn transistor_paths(transistors: &[(usize, usize)]) -> impl Iterator<Item = (usize, usize)> + '_ {
+    transistors
+        .iter()
+        .flat_map(|&(first, second)| [(first, second), (second, first)])
+}
+
+/// Builds and solves the nodal equations `G * v = i` for the given junction states.
+fn solve_nodes(
+    parts: &[Part],
+    transistors: &[(usize, usize)],
+    conducting: &[bool],
+    charges: Option<&Charges>,
+    node_count: usize,
+) -> Vec<f64> {
+    let n = node_count;
+    let mut g = vec![0.0; n * n];
+    let mut i = vec![0.0; n];
+
+    for node in 0..n {
+        g[node * n + node] += GMIN;
+    }
+
+    let mut conductance = |a: usize, b: usize, value: f64| {
+        g[a * n + a] += value;
+        g[b * n + b] += value;
+        g[a * n + b] -= value;
+        g[b * n + a] -= value;
+    };
+
+    // Current sources are stored as (into, out of, amperes)
+    let mut sources = Vec::new();
+
+    for (part, &on) in parts.iter().zip(conducting.iter()) {
+        match part.element {
+            Element::Cable => conductance(part.a, part.b, 1.0 / CABLE_RESISTANCE),
+            Element::Lamp => conductance(part.a, part.b, 1.0 / LAMP_RESISTANCE),
+            Element::Resistor(ohms) => conductance(part.a, part.b, 1.0 / resistance(ohms)),
+            Element::Bulk => conductance(part.a, part.b, 1.0 / BULK_RESISTANCE),
+            Element::Battery => {
+                // Norton equivalent of a source with internal resistance
+                conductance(part.a, part.b, 1.0 / BATTERY_RESISTANCE);
+                sources.push((part.a, part.b, BATTERY_VOLTS / BATTERY_RESISTANCE));
+            }
+            Element::Junction(_) | Element::Led => {
+                let (forward_volts, on_resistance) = part.element.diode().unwrap();
+                match on {
+                    true => {
+                        conductance(part.a, part.b, 1.0 / on_resistance);
+                        sources.push((part.a, part.b, forward_volts / on_resistance));
+                    }
+                    false => conductance(part.a, part.b, DIODE_OFF_CONDUCTANCE),
+                }
+            }
+            Element::Capacitor => {
+                // Charging: a source with the voltage of its charge, like a battery
+                if charges.is_some() {
+                    conductance(part.a, part.b, 1.0 / CAPACITOR_RESISTANCE);
+                    let volts = charge(charges, part.cell);
+                    sources.push((part.a, part.b, volts / CAPACITOR_RESISTANCE));
+                }
+            }
+        }
+    }
+
+    // Transistors: a share of the emitter current, which depends on the emitter junction's voltage,
+    // flows from the N side to the P side of the collector junction
+    for (emitter, collector) in transistor_paths(transistors) {
+        if !conducting[emitter] {
+            continue;
+        }
+        let (emitter, collector) = (&parts[emitter], &parts[collector]);
+        let (forward_volts, on_resistance) = emitter.element.diode().unwrap();
+        let gain = TRANSISTOR_ALPHA / on_resistance;
+
+        g[collector.b * n + emitter.a] += gain;
+        g[collector.b * n + emitter.b] -= gain;
+        g[collector.a * n + emitter.a] -= gain;
+        g[collector.a * n + emitter.b] += gain;
+        sources.push((collector.b, collector.a, gain * forward_volts));
+    }
+
+    for (into, out, amperes) in sources {
+        i[into] += amperes;
+        i[out] -= amperes;
+    }
+
+    solve_linear(g, i, n)
+}
+
+/// Gaussian elimination with partial pivoting on the row-major `n * n` matrix `a`.
+fn solve_linear(mut a: Vec<f64>, mut b: Vec<f64>, n: usize) -> Vec<f64> {
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&r1, &r2| a[r1 * n + col].abs().total_cmp(&a[r2 * n + col].abs()))
+            .unwrap();
+        if pivot != col {
+            for k in 0..n {
+                a.swap(col * n + k, pivot * n + k);
+            }
+            b.swap(col, pivot);
+        }
+
+        let diagonal = a[col * n + col];
+        if diagonal == 0.0 {
+            continue;
+        }
+
+        for row in col + 1..n {
+            let factor = a[row * n + col] / diagonal;
+            if factor == 0.0 {
+                continue;
+            }
+            for k in col..n {
+                a[row * n + k] -= factor * a[col * n + k];
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[row * n + k] * x[k]).sum();
+        let diagonal = a[row * n + row];
+        x[row] = if diagonal == 0.0 {
+            0.0
+        } else {
+            (b[row] - sum) / diagonal
+        };
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::Goal;
+    use crate::level::tests::shipped_levels;
+
+    /// Board of the given width from a RON list of tiles, row by row.
+    fn grid(width: usize, tiles: &str) -> Grid {
+        let tiles: Vec<Option<Tile>> = ron::from_str(tiles).unwrap();
+        Grid {
+            tiles: grid::Grid::from_vec(tiles, width),
+            goal: Goal::AllLamps,
+        }
+    }
+
+    fn battery_currents(analysis: &Analysis) -> Vec<f64> {
+        analysis
+            .parts
+            .iter()
+            .zip(analysis.currents.iter())
+            .filter(|(part, _)| part.element == Element::Battery)
+            .map(|(_, current)| *current)
+            .collect()
+    }
+
+    /// Current through the only part of the tile at `cell`.
+    fn current_through(analysis: &Analysis, cell: (usize, usize)) -> f64 {
+        analysis
+            .parts
+            .iter()
+            .zip(analysis.currents.iter())
+            .find(|(part, _)| part.cell == cell)
+            .map(|(_, current)| current.abs())
+            .unwrap()
+    }
+
+    #[test]
+    fn solved_levels_meet_their_goal() {
+        let levels = shipped_levels();
+        assert!(!levels.is_empty());
+
+        for (file, level) in levels {
+            let grid = Grid::new(&level);
+            let analysis = analyze(&grid);
+
+            assert!(grid.goal.is_met(&grid, &analysis), "{file} is not solved");
+            assert!(!analysis.is_short_circuit(), "{file} is short-circuited");
+        }
+    }
+
+    #[test]
+    fn reverse_biased_junction_blocks() {
+        // The easy level with the battery turned around
+        let analysis = analyze(&grid(
+            3,
+            "[
+                Some(Cable(entry: Right, exit: Bottom)),
+                Some(Battery(plus_side: Right, minus_side: Left)),
+                Some(N),
+                Some(Cable(entry: Top, exit: Right)),
+                Some(Lamp(entry: Left, exit: Bottom)),
+                Some(P),
+                None,
+                Some(Cable(entry: Top, exit: Right)),
+                Some(Cable(entry: Left, exit: Top)),
+            ]",
+        ));
+
+        assert!(!analysis.is_lit((1, 1)));
+        assert!(analysis.lamp_current((1, 1)) < 1e-6);
+        assert!(analysis.depletion_width((2, 1), Side::Top).unwrap() > 1.0);
+    }
+
+    #[test]
+    fn forward_biased_junction_conducts() {
+        let analysis = analyze(&grid(
+            3,
+            "[
+                Some(Cable(entry: Right, exit: Bottom)),
+                Some(Battery(plus_side: Left, minus_side: Right)),
+                Some(N),
+                Some(Cable(entry: Top, exit: Right)),
+                Some(Lamp(entry: Left, exit: Bottom)),
+                Some(P),
+                None,
+                Some(Cable(entry: Top, exit: Right)),
+                Some(Cable(entry: Left, exit: Top)),
+            ]",
+        ));
+
+        assert!(analysis.is_lit((1, 1)));
+        assert_eq!(
+            analysis.depletion_width((2, 1), Side::Top),
+            Some(MIN_DEPLETION_WIDTH)
+        );
+    }
+
+    #[test]
+    fn cable_across_battery_is_short_circuit() {
+        let analysis = analyze(&grid(
+            2,
+            "[
+                Some(Battery(plus_side: Right, minus_side: Bottom)),
+                Some(Cable(entry: Left, exit: Bottom)),
+                Some(Cable(entry: Right, exit: Top)),
+                Some(Cable(entry: Top, exit: Left)),
+            ]",
+        ));
+
+        assert!(analysis.is_short_circuit());
+    }
+
+    #[test]
+    fn open_switch_breaks_the_loop() {
+        let board = |closed: bool| {
+            grid(
+                2,
+                &format!(
+                    "[
+                        Some(Battery(plus_side: Right, minus_side: Bottom)),
+                        Some(Switch(entry: Left, exit: Bottom, closed: {closed})),
+                        Some(Cable(entry: Right, exit: Top)),
+                        Some(Lamp(entry: Top, exit: Left)),
+                    ]"
+                ),
+            )
+        };
+
+        let open = analyze(&board(false));
+        assert!(!open.is_lit((1, 1)));
+        assert_eq!(open.lamp_current((1, 1)), 0.0);
+
+        let closed = analyze(&board(true));
+        assert!(closed.is_lit((1, 1)));
+    }
+
+    #[test]
+    fn capacitor_only_lights_the_lamp_while_charging() {
+        let grid = grid(
+            2,
+            "[
+                Some(Battery(plus_side: Right, minus_side: Bottom)),
+                Some(Capacitor(entry: Left, exit: Bottom)),
+                Some(Cable(entry: Right, exit: Top)),
+                Some(Lamp(entry: Top, exit: Left)),
+            ]",
+        );
+
+        assert!(simulate(&grid, &Charges::new()).is_lit((1, 1)));
+        assert!(!analyze(&grid).is_lit((1, 1)));
+        assert!(!grid.is_solved());
+    }
+
+    #[test]
+    fn only_a_capacitor_makes_the_lamp_flash() {
+        let board = |part: &str| Grid {
+            goal: Goal::Flash,
+            ..grid(
+                2,
+                &format!(
+                    "[
+                        Some(Battery(plus_side: Right, minus_side: Bottom)),
+                        Some({part}(entry: Left, exit: Bottom)),
+                        Some(Cable(entry: Right, exit: Top)),
+                        Some(Lamp(entry: Top, exit: Left)),
+                    ]"
+                ),
+            )
+        };
+
+        assert!(board("Capacitor").is_solved());
+
+        // A cable keeps the lamp lit, so it never goes dark again
+        let cable = board("Cable");
+        assert!(analyze(&cable).is_lit((1, 1)));
+        assert!(!cable.is_solved());
+    }
+
+    #[test]
+    fn batteries_in_series_add_up() {
+        let analysis = analyze(&grid(
+            2,
+            "[
+                Some(Battery(plus_side: Right, minus_side: Bottom)),
+                Some(Battery(plus_side: Bottom, minus_side: Left)),
+                Some(Cable(entry: Right, exit: Top)),
+                Some(Lamp(entry: Top, exit: Left)),
+            ]",
+        ));
+
+        assert!(analysis.is_lit((1, 1)));
+        assert!(analysis.lamp_current((1, 1)) > 1.5 * LAMP_NOMINAL_CURRENT);
+        assert!(!analysis.is_short_circuit());
+    }
+
+    #[test]
+    fn opposed_batteries_cancel_out() {
+        let analysis = analyze(&grid(
+            2,
+            "[
+                Some(Battery(plus_side: Right, minus_side: Bottom)),
+                Some(Battery(plus_side: Left, minus_side: Bottom)),
+                Some(Cable(entry: Right, exit: Top)),
+                Some(Lamp(entry: Top, exit: Left)),
+            ]",
+        ));
+
+        assert!(!analysis.is_lit((1, 1)));
+        assert!(analysis.lamp_current((1, 1)) < 1e-6);
+        assert!(!analysis.is_short_circuit());
+    }
+
+    #[test]
+    fn parallel_batteries_share_the_load() {
+        let analysis = analyze(&grid(
+            3,
+            "[
+                Some(Battery(plus_side: Right, minus_side: Bottom)),
+                Some(TJunction(missing: Top)),
+                Some(Battery(plus_side: Left, minus_side: Bottom)),
+                Some(Cable(entry: Top, exit: Bottom)),
+                Some(Lamp(entry: Top, exit: Bottom)),
+                Some(Cable(entry: Top, exit: Bottom)),
+                Some(Cable(entry: Top, exit: Right)),
+                Some(TJunction(missing: Bottom)),
+                Some(Cable(entry: Top, exit: Left)),
+            ]",
+        ));
+
+        let lamp = analysis.lamp_current((1, 1));
+        assert!(analysis.is_lit((1, 1)));
+        assert!(lamp > LAMP_NOMINAL_CURRENT && lamp < 1.1 * LAMP_NOMINAL_CURRENT);
+        for current in battery_currents(&analysis) {
+            assert!((current.abs() - lamp / 2.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn base_current_switches_the_transistor() {
+        // The lamp feeds the collector of an NPN transistor, `base` is what sits next to its base
+        let board = |base: &str| {
+            grid(
+                3,
+                &format!(
+                    "[
+                        Some(Lamp(entry: Bottom, exit: Right)),
+                        Some(N),
+                        Some(Cable(entry: Left, exit: Bottom)),
+                        Some(Battery(plus_side: Top, minus_side: Bottom)),
+                        Some(P),
+                        Some({base}),
+                        Some(Cable(entry: Top, exit: Right)),
+                        Some(N),
+                        None,
+                    ]"
+                ),
+            )
+        };
+
+        // A small base current through the resistor lets a large collector current through the lamp
+        let on = analyze(&board("Resistor(entry: Top, exit: Left, ohms: 100)"));
+        assert!(on.is_lit((0, 0)));
+        assert!(on.lamp_current((0, 0)) > 10.0 * current_through(&on, (2, 1)));
+
+        // Without base current the collector junction blocks
+        let off = analyze(&board("Cable(entry: Top, exit: Bottom)"));
+        assert!(!off.is_lit((0, 0)));
+        assert!(off.lamp_current((0, 0)) < 1e-6);
+    }
+
+    #[test]
+    fn led_only_glows_forward_biased() {
+        let board = |anode: &str, cathode: &str| {
+            grid(
+                2,
+                &format!(
+                    "[
+                        Some(Battery(plus_side: Right, minus_side: Bottom)),
+                        Some(Led(anode: {anode}, cathode: {cathode}, color: Red)),
+                        Some(Cable(entry: Right, exit: Top)),
+                        Some(Cable(entry: Top, exit: Left)),
+                    ]"
+                ),
+            )
+        };
+
+        let forward = analyze(&board("Left", "Bottom"));
+        assert!(forward.is_lit((1, 0)));
+        assert!(forward.led_current((1, 0)) > LED_MIN_CURRENT);
+
+        let reverse = analyze(&board("Bottom", "Left"));
+        assert!(!reverse.is_lit((1, 0)));
+        assert!(reverse.led_current((1, 0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn resistor_dims_the_lamp() {
+        let board = |ohms: u32| {
+            grid(
+                2,
+                &format!(
+                    "[
+                        Some(Battery(plus_side: Right, minus_side: Bottom)),
+                        Some(Resistor(entry: Left, exit: Bottom, ohms: {ohms})),
+                        Some(Cable(entry: Right, exit: Top)),
+                        Some(Lamp(entry: Top, exit: Left)),
+                    ]"
+                ),
+            )
+        };
+
+        let none = analyze(&board(0));
+        let small = analyze(&board(5));
+        let large = analyze(&board(100));
+
+        assert!(none.is_lit((1, 1)) && small.is_lit((1, 1)));
+        assert!(small.brightness((1, 1)) < none.brightness((1, 1)));
+        assert!(!large.is_lit((1, 1)));
+        assert!(large.lamp_current((1, 1)) < small.lamp_current((1, 1)));
+    }
+
+    #[test]
+    fn crossover_keeps_its_wires_apart() {
+        // The battery's plus pole runs down through the middle tile, its minus pole runs into the
+        // middle tile from the left
+        let board = |middle: &str| {
+            grid(
+                3,
+                &format!(
+                    "[
+                        Some(Cable(entry: Right, exit: Bottom)),
+                        Some(Battery(plus_side: Bottom, minus_side: Left)),
+                        None,
+                        Some(TJunction(missing: Left)),
+                        Some({middle}),
+                        None,
+                        Some(Cable(entry: Top, exit: Right)),
+                        Some(Lamp(entry: Top, exit: Left)),
+                        None,
+                    ]"
+                ),
+            )
+        };
+
+        let crossover = analyze(&board("Crossover"));
+        assert!(crossover.is_lit((1, 2)));
+        assert!(!crossover.is_short_circuit());
+
+        // A cross joins both wires and connects the poles
+        let cross = analyze(&board("Cross"));
+        assert!(cross.is_short_circuit());
+        assert!(!cross.is_lit((1, 2)));
+    }
+}
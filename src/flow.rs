@@ -0,0 +1,138 @@
+use crate::circuit::{self, PathPoint};
+use crate::{BoardLayout, CircuitChanged, Grid, Side};
+use bevy::prelude::*;
+
+/// Distance between two particles, as a share of the tile size.
+const PARTICLE_SPACING: f32 = 0.25;
+/// Tiles per second.
+const PARTICLE_SPEED: f32 = 0.8;
+const PARTICLE_RADIUS: f32 = 0.04;
+
+/// Colours of the charge carriers, both in the current loop and inside P and N tiles.
+pub const HOLE_COLOR: Color = Color::srgb(1.0, 0.3, 0.3);
+pub const ELECTRON_COLOR: Color = Color::srgb(0.3, 0.8, 1.0);
+
+/// Which charges are shown moving around a closed circuit.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CurrentConvention {
+    /// Positive charges (holes) from plus to minus.
+    #[default]
+    Conventional,
+    /// Electrons from minus to plus.
+    Electrons,
+}
+
+impl CurrentConvention {
+    pub fn toggled(&self) -> CurrentConvention {
+        match self {
+            CurrentConvention::Conventional => CurrentConvention::Electrons,
+            CurrentConvention::Electrons => CurrentConvention::Conventional,
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            CurrentConvention::Conventional => "Stromrichtung: technisch (+ nach -)",
+            CurrentConvention::Electrons => "Stromrichtung: Elektronen (- nach +)",
+        }
+    }
+}
+
+/// Loop of the current through the solved board in world coordinates, empty while the puzzle is
+/// not solved.
+#[derive(Resource, Default)]
+pub struct CurrentFlow {
+    points: Vec<Vec2>,
+}
+
+pub fn update_current_flow(
+    _event: On<CircuitChanged>,
+    mut commands: Commands,
+    grid: Res<Grid>,
+    layout: Res<BoardLayout>,
+) {
+    let analysis = circuit::analyze(&grid);
+
+    let points = match grid.is_solved() {
+        true => analysis
+            .current_path()
+            .iter()
+            .map(|point| world_pos(&layout, point))
+            .collect(),
+        false => Vec::new(),
+    };
+    commands.insert_resource(CurrentFlow { points });
+}
+
+fn world_pos(layout: &BoardLayout, point: &PathPoint) -> Vec2 {
+    let (x, y) = point.cell;
+    let tile_size = layout.tile_size();
+    let offset = match point.side {
+        Some(Side::Left) => Vec2::new(0.0, -tile_size / 2.0),
+        Some(Side::Right) => Vec2::new(tile_size, -tile_size / 2.0),
+        Some(Side::Top) => Vec2::new(tile_size / 2.0, 0.0),
+        Some(Side::Bottom) => Vec2::new(tile_size / 2.0, -tile_size),
+        None => Vec2::new(tile_size / 2.0, -tile_size / 2.0),
+    };
+    layout.world_pos_from_xy(x, y).unwrap() + offset
+}
+
+pub fn toggle_convention_listener(
+    input: Res<ButtonInput<KeyCode>>,
+    mut convention: ResMut<CurrentConvention>,
+) {
+    if input.just_pressed(KeyCode::KeyC) {
+        *convention = convention.toggled();
+    }
+}
+
+/// Moves evenly spaced particles along the current loop.
+pub fn draw_current_flow(
+    flow: Res<CurrentFlow>,
+    convention: Res<CurrentConvention>,
+    layout: Res<BoardLayout>,
+    time: Res<Time>,
+    mut gizmos: Gizmos,
+) {
+    let length: f32 = flow
+        .points
+        .windows(2)
+        .map(|segment| segment[0].distance(segment[1]))
+        .sum();
+    if length <= 0.0 {
+        return;
+    }
+
+    let tile_size = layout.tile_size();
+    let spacing = length / (length / (PARTICLE_SPACING * tile_size)).round().max(1.0);
+    let offset = (time.elapsed_secs() * PARTICLE_SPEED * tile_size) % spacing;
+    let color = match *convention {
+        CurrentConvention::Conventional => HOLE_COLOR,
+        CurrentConvention::Electrons => ELECTRON_COLOR,
+    };
+
+    let mut distance = offset;
+    while distance < length {
+        let along = match *convention {
+            CurrentConvention::Conventional => distance,
+            CurrentConvention::Electrons => length - distance,
+        };
+        gizmos.circle_2d(
+            point_along(&flow.points, along),
+            PARTICLE_RADIUS * tile_size,
+            color,
+        );
+        distance += spacing;
+    }
+}
+
+fn point_along(points: &[Vec2], mut distance: f32) -> Vec2 {
+    for segment in points.windows(2) {
+        let length = segment[0].distance(segment[1]);
+        if distance <= length && length > 0.0 {
+            return segment[0].lerp(segment[1], distance / length);
+        }
+        distance -= length;
+    }
+    *points.last().unwrap()
+}
@@ -0,0 +1,209 @@
+use crate::flow::CurrentConvention;
+use crate::{
+    AppState, BoardLayout, Grid, HintRequest, LevelState, MakeNewPuzzleRequest, Move, MoveHistory,
+    MoveKind, MoveTileRequest, Press, Side, Sounds,
+};
+use bevy::audio::Volume;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+/// How far the stick has to be pushed to count as a direction.
+const STICK_THRESHOLD: f32 = 0.5;
+
+const FOCUS_COLOR: Color = Color::WHITE;
+const CURSOR_COLOR: Color = Color::srgb(0.3, 0.8, 1.0);
+
+/// Buttons of the intro or menu screen that the gamepad moves through, in reading order.
+#[derive(Resource)]
+pub struct MenuFocus {
+    buttons: Vec<Entity>,
+    selected: usize,
+}
+
+impl MenuFocus {
+    pub fn new(buttons: Vec<Entity>) -> MenuFocus {
+        MenuFocus {
+            buttons,
+            selected: 0,
+        }
+    }
+}
+
+/// Cell of the tile selected with the gamepad.
+#[derive(Resource, Default)]
+pub struct GamepadCursor {
+    cell: (usize, usize),
+}
+
+/// Direction each gamepad's D-pad or left stick points in, so holding it only moves once.
+#[derive(Default)]
+pub struct HeldDirections(HashMap<Entity, Option<Side>>);
+
+impl HeldDirections {
+    /// The direction the gamepad was just pushed in, `None` while it is released or still held.
+    fn just_pushed(&mut self, entity: Entity, gamepad: &Gamepad) -> Option<Side> {
+        let direction = gamepad.dpad() + gamepad.left_stick();
+        let side = if direction.abs().max_element() < STICK_THRESHOLD {
+            None
+        } else if direction.x.abs() > direction.y.abs() {
+            Some(if direction.x > 0.0 {
+                Side::Right
+            } else {
+                Side::Left
+            })
+        } else {
+            Some(if direction.y > 0.0 {
+                Side::Top
+            } else {
+                Side::Bottom
+            })
+        };
+
+        let previous = self.0.insert(entity, side).flatten();
+        side.filter(|&side| previous != Some(side))
+    }
+}
+
+/// Moves the focus between the menu buttons and presses the focused one with South (A).
+pub fn navigate_menu(
+    mut held: Local<HeldDirections>,
+    gamepads: Query<(Entity, &Gamepad)>,
+    mut focus: ResMut<MenuFocus>,
+    mut borders: Query<&mut BorderColor>,
+    mut commands: Commands,
+) {
+    if gamepads.is_empty() || focus.buttons.is_empty() {
+        return;
+    }
+
+    let count = focus.buttons.len();
+    for (entity, gamepad) in gamepads.iter() {
+        match held.just_pushed(entity, gamepad) {
+            Some(Side::Top | Side::Left) => focus.selected = (focus.selected + count - 1) % count,
+            Some(Side::Bottom | Side::Right) => focus.selected = (focus.selected + 1) % count,
+            None => {}
+        }
+
+        if gamepad.just_pressed(GamepadButton::South) {
+            commands.trigger(Press {
+                entity: focus.buttons[focus.selected],
+            });
+        }
+    }
+
+    for (index, &button) in focus.buttons.iter().enumerate() {
+        if let Ok(mut border) = borders.get_mut(button) {
+            *border = match index == focus.selected {
+                true => BorderColor::all(FOCUS_COLOR),
+                false => BorderColor::all(Color::BLACK),
+            };
+        }
+    }
+}
+
+/// Moves the cursor over the board and plays with the selected tile:
+///
+/// - South (A) slides it into the empty cell
+/// - West (X) toggles a switch
+/// - North (Y) shows a hint, East (B) switches the current direction shown
+/// - Left and right trigger undo and redo
+/// - Start restarts the puzzle, Select goes back to the menu
+#[allow(clippy::too_many_arguments)]
+pub fn play_with_gamepad(
+    mut held: Local<HeldDirections>,
+    gamepads: Query<(Entity, &Gamepad)>,
+    mut cursor: ResMut<GamepadCursor>,
+    mut history: ResMut<MoveHistory>,
+    mut convention: ResMut<CurrentConvention>,
+    grid: Res<Grid>,
+    sounds: Res<Sounds>,
+    mut next_app_state: ResMut<NextState<AppState>>,
+    mut next_level_state: ResMut<NextState<LevelState>>,
+    mut commands: Commands,
+) {
+    for (entity, gamepad) in gamepads.iter() {
+        if let Some(side) = held.just_pushed(entity, gamepad) {
+            let (x, y) = cursor.cell;
+            let x = x.saturating_add_signed(side.x_offset() as isize);
+            let y = y.saturating_add_signed(side.y_offset() as isize);
+            cursor.cell = (x.min(grid.width() - 1), y.min(grid.height() - 1));
+        }
+
+        let mut mv = None;
+        if gamepad.just_pressed(GamepadButton::South) {
+            mv = grid.empty_cell().map(|to| Move::Slide {
+                from: cursor.cell,
+                to,
+            });
+        }
+        if gamepad.just_pressed(GamepadButton::West) {
+            mv = Some(Move::Toggle { cell: cursor.cell });
+        }
+
+        match mv {
+            Some(mv) if grid.is_legal_move(mv) => {
+                // Stay on the tile that was moved
+                if let Move::Slide { to, .. } = mv {
+                    cursor.cell = to;
+                }
+
+                commands.trigger(MoveTileRequest {
+                    mv,
+                    kind: MoveKind::Play,
+                });
+            }
+            Some(_) => {
+                // Audio
+                commands.spawn((AudioPlayer::new(sounds.misdrop.clone()), {
+                    let mut settings = PlaybackSettings::DESPAWN;
+                    settings.volume = Volume::Linear(0.2);
+                    settings
+                }));
+            }
+            None => {}
+        }
+
+        if gamepad.just_pressed(GamepadButton::North) {
+            commands.trigger(HintRequest);
+        }
+        if gamepad.just_pressed(GamepadButton::East) {
+            *convention = convention.toggled();
+        }
+
+        if gamepad.just_pressed(GamepadButton::LeftTrigger) {
+            history.undo(&mut commands);
+        }
+        if gamepad.just_pressed(GamepadButton::RightTrigger) {
+            history.redo(&mut commands);
+        }
+
+        if gamepad.just_pressed(GamepadButton::Start) {
+            commands.trigger(MakeNewPuzzleRequest);
+        }
+        if gamepad.just_pressed(GamepadButton::Select) {
+            next_app_state.set(AppState::Menu);
+            next_level_state.set(LevelState::Menu);
+        }
+    }
+}
+
+/// Frames the selected tile while a gamepad is connected.
+pub fn draw_gamepad_cursor(
+    gamepads: Query<&Gamepad>,
+    cursor: Res<GamepadCursor>,
+    layout: Res<BoardLayout>,
+    mut gizmos: Gizmos,
+) {
+    if gamepads.is_empty() {
+        return;
+    }
+
+    let (x, y) = cursor.cell;
+    let Some(corner) = layout.world_pos_from_xy(x, y) else {
+        return;
+    };
+    let tile_size = Vec2::splat(layout.tile_size());
+    let center = corner + Vec2::new(tile_size.x, -tile_size.y) / 2.0;
+
+    gizmos.rect_2d(center, tile_size * 0.9, CURSOR_COLOR);
+}
@@ -0,0 +1,276 @@
+use crate::flow::CurrentConvention;
+use crate::level::Level;
+use crate::{Grid, LevelState, MakeNewPuzzleRequest, Move, MoveKind, TileMoved, is_portrait};
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Records file in the game's own folder of the platform's data directory.
+#[cfg(not(target_arch = "wasm32"))]
+const RECORDS_FILE: &str = "halbleiter/records.ron";
+/// Key of the records in the browser's local storage.
+#[cfg(target_arch = "wasm32")]
+const RECORDS_KEY: &str = "halbleiter_records";
+
+/// Progress of the current puzzle, reset whenever a new puzzle is requested.
+#[derive(Resource)]
+pub struct PuzzleStats {
+    moves: u32,
+    mistakes: u32,
+    started: Duration,
+    finished: Option<Duration>,
+    new_record: bool,
+    /// The records of this puzzle could not be saved, so they are lost once the game is closed.
+    save_failed: bool,
+}
+
+#[derive(Event)]
+pub struct PuzzleSolved;
+
+#[derive(Event)]
+pub struct ShortCircuit;
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug)]
+pub struct Record {
+    moves: Option<u32>,
+    seconds: Option<f32>,
+}
+
+/// Best move count and time per level, kept between runs in `RECORDS_FILE`, or in the browser's
+/// local storage on the web. Levels are told apart by their name.
+#[derive(Resource, Serialize, Deserialize, Default, Debug)]
+pub struct Records(HashMap<String, Record>);
+
+impl Records {
+    fn load() -> Records {
+        let Some(text) = read_records() else {
+            return Records::default();
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("Could not parse records: {}", err);
+            Records::default()
+        })
+    }
+
+    fn save(&self) -> Result<(), String> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())
+            .and_then(|text| write_records(&text))
+    }
+}
+
+/// The user's data directory, the working directory of an installed game is often read-only.
+#[cfg(not(any(target_arch = "wasm32", target_os = "android")))]
+fn records_path() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join(RECORDS_FILE))
+}
+
+/// The app's internal storage, the only place an Android app can always write to.
+#[cfg(target_os = "android")]
+fn records_path() -> Option<PathBuf> {
+    let app = bevy::android::ANDROID_APP.get()?;
+    Some(app.internal_data_path()?.join(RECORDS_FILE))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_records() -> Option<String> {
+    std::fs::read_to_string(records_path()?).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_records(text: &str) -> Result<(), String> {
+    let path = records_path().ok_or_else(|| "no data directory".to_string())?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+    }
+    std::fs::write(&path, text).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok().flatten()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_records() -> Option<String> {
+    local_storage()?.get_item(RECORDS_KEY).ok().flatten()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_records(text: &str) -> Result<(), String> {
+    let storage = local_storage().ok_or_else(|| "no local storage".to_string())?;
+    storage
+        .set_item(RECORDS_KEY, text)
+        .map_err(|err| format!("{:?}", err))
+}
+
+pub fn load_records(mut commands: Commands) {
+    commands.insert_resource(Records::load());
+}
+
+#[derive(Component)]
+#[require(Text)]
+pub struct HudText;
+
+/// Top right, or below the board on a portrait screen.
+pub fn hud_node(screen: UVec2) -> Node {
+    match is_portrait(screen) {
+        true => Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(20.0),
+            bottom: Val::Px(10.0),
+            ..default()
+        },
+        false => Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(20.0),
+            top: Val::Px(10.0),
+            ..default()
+        },
+    }
+}
+
+pub fn spawn_hud(mut commands: Commands, window: Single<&Window, With<PrimaryWindow>>) {
+    commands.spawn((
+        HudText,
+        hud_node(window.size().as_uvec2()),
+        TextFont {
+            font_size: 23.0,
+            ..default()
+        },
+        TextColor(Color::WHITE),
+        TextLayout::new_with_justify(Justify::Right),
+    ));
+}
+
+pub fn reset_puzzle_stats(
+    _event: On<MakeNewPuzzleRequest>,
+    mut commands: Commands,
+    time: Res<Time>,
+) {
+    commands.insert_resource(PuzzleStats {
+        moves: 0,
+        mistakes: 0,
+        started: time.elapsed(),
+        finished: None,
+        new_record: false,
+        save_failed: false,
+    });
+}
+
+/// Counts the tiles the player slid. Undo and redo don't count, they only step through moves
+/// that were counted already, and neither does flipping a switch.
+pub fn count_move(moved: On<TileMoved>, stats: Option<ResMut<PuzzleStats>>) {
+    let played_slide = moved.kind == MoveKind::Play && matches!(moved.mv, Move::Slide { .. });
+    if let Some(mut stats) = stats
+        && stats.finished.is_none()
+        && played_slide
+    {
+        stats.moves += 1;
+    }
+}
+
+pub fn count_mistake(
+    _event: On<ShortCircuit>,
+    stats: Option<ResMut<PuzzleStats>>,
+    level_assets: Res<Assets<Level>>,
+    level_state: Res<State<LevelState>>,
+) {
+    let Some(level) = level_state.level(&level_assets) else {
+        return;
+    };
+    if let Some(mut stats) = stats
+        && stats.finished.is_none()
+        && level.count_mistakes
+    {
+        stats.mistakes += 1;
+    }
+}
+
+pub fn finish_puzzle(
+    _event: On<PuzzleSolved>,
+    stats: Option<ResMut<PuzzleStats>>,
+    mut records: ResMut<Records>,
+    level_assets: Res<Assets<Level>>,
+    level_state: Res<State<LevelState>>,
+    time: Res<Time>,
+) {
+    let (Some(mut stats), Some(level)) = (stats, level_state.level(&level_assets)) else {
+        return;
+    };
+    if stats.finished.is_some() {
+        return;
+    }
+
+    let finished = time.elapsed();
+    let seconds = (finished - stats.started).as_secs_f32();
+    stats.finished = Some(finished);
+
+    let record = records.0.entry(level.name.clone()).or_default();
+    let beats_moves = record.moves.is_some_and(|best| stats.moves < best);
+    let beats_time = record.seconds.is_some_and(|best| seconds < best);
+    stats.new_record = beats_moves || beats_time;
+
+    record.moves = Some(
+        record
+            .moves
+            .map_or(stats.moves, |best| best.min(stats.moves)),
+    );
+    record.seconds = Some(record.seconds.map_or(seconds, |best| best.min(seconds)));
+    if let Err(err) = records.save() {
+        warn!("Could not save records: {}", err);
+        stats.save_failed = true;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_hud(
+    mut hud: Single<&mut Text, With<HudText>>,
+    stats: Res<PuzzleStats>,
+    grid: Option<Res<Grid>>,
+    records: Res<Records>,
+    convention: Res<CurrentConvention>,
+    level_assets: Res<Assets<Level>>,
+    level_state: Res<State<LevelState>>,
+    time: Res<Time>,
+) {
+    let elapsed = stats.finished.unwrap_or(time.elapsed()) - stats.started;
+    let mut text = format!(
+        "Zuege: {}\nZeit: {:.1}s",
+        stats.moves,
+        elapsed.as_secs_f32()
+    );
+
+    if stats.mistakes > 0 {
+        text += &format!("\nFehler: {}", stats.mistakes);
+    }
+
+    if let Some(grid) = grid {
+        text = format!("{}\n{}", grid.goal.describe(), text);
+    }
+
+    let level = level_state.level(&level_assets);
+    if let Some(record) = level.and_then(|level| records.0.get(&level.name)) {
+        if let Some(moves) = record.moves {
+            text += &format!("\nBeste Zuege: {}", moves);
+        }
+        if let Some(seconds) = record.seconds {
+            text += &format!("\nBeste Zeit: {:.1}s", seconds);
+        }
+    }
+
+    if stats.new_record {
+        text += "\nNeuer Rekord!";
+    }
+    if stats.save_failed {
+        text += "\nRekorde konnten nicht gespeichert werden";
+    }
+
+    text += &format!("\n{}", convention.describe());
+
+    hud.0 = text;
+}
@@ -0,0 +1,461 @@
+use crate::circuit::{self, Analysis, Charges};
+use crate::{Grid, Side, Tile};
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext, LoadedFolder};
+use bevy::prelude::*;
+use serde::Deserialize;
+use thiserror::Error;
+
+/// A puzzle board as described by a `*.level.ron` file in `assets/levels`.
+///
+/// `tiles` is the solved layout in row-major order, `None` marks the empty cell.
+#[derive(Asset, TypePath, Deserialize, Debug, Clone)]
+pub struct Level {
+    pub name: String,
+    pub difficulty: Difficulty,
+    pub width: usize,
+    pub height: usize,
+    pub tiles: Vec<Option<Tile>>,
+    #[serde(default)]
+    pub goal: Goal,
+    /// Counts every move that leaves the board short-circuited as a mistake.
+    #[serde(default)]
+    pub count_mistakes: bool,
+}
+
+/// Which lamps have to glow for the board to count as solved. LEDs count as lamps without an id.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Goal {
+    /// Every lamp on the board.
+    #[default]
+    AllLamps,
+    /// Exactly the lamps with these ids, all other lamps stay dark.
+    Lamps(Vec<u8>),
+    /// Exactly this many lamps, no matter which.
+    LampCount(usize),
+    /// Every lamp lights up while the capacitors charge right after the board is arranged, and is
+    /// dark again once they are charged.
+    Flash,
+}
+
+impl Level {
+    /// Checks everything the game relies on but the file format can't express.
+    fn validate(&self) -> Result<(), LevelLoaderError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(LevelLoaderError::NoCells {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        if self.width * self.height != self.tiles.len() {
+            return Err(LevelLoaderError::Size {
+                width: self.width,
+                height: self.height,
+                tiles: self.tiles.len(),
+            });
+        }
+
+        let empty_cells = self.tiles.iter().filter(|tile| tile.is_none()).count();
+        if empty_cells != 1 {
+            return Err(LevelLoaderError::EmptyCells(empty_cells));
+        }
+
+        for (index, tile) in self.tiles.iter().enumerate() {
+            if let Some((first, second)) = tile.as_ref().and_then(ends)
+                && first == second
+            {
+                return Err(LevelLoaderError::SameSide {
+                    x: index % self.width,
+                    y: index / self.width,
+                    side: first,
+                });
+            }
+        }
+
+        self.validate_goal()
+    }
+
+    /// Checks that the goal can be met at all and never counts a board with every lamp dark as
+    /// solved, or the scrambler could not scramble the level.
+    fn validate_goal(&self) -> Result<(), LevelLoaderError> {
+        let lamps = self
+            .tiles
+            .iter()
+            .filter(|tile| matches!(tile, Some(Tile::Lamp { .. } | Tile::Led { .. })))
+            .count();
+        if lamps == 0 {
+            return Err(LevelLoaderError::NoLamps);
+        }
+
+        match &self.goal {
+            Goal::AllLamps => Ok(()),
+            Goal::Lamps(ids) if ids.is_empty() => Err(LevelLoaderError::NothingToLight),
+            Goal::Lamps(ids) => {
+                let known: Vec<u8> = self
+                    .tiles
+                    .iter()
+                    .filter_map(|tile| match tile {
+                        Some(Tile::Lamp { id, .. }) => *id,
+                        _ => None,
+                    })
+                    .collect();
+                match ids.iter().find(|id| !known.contains(id)) {
+                    Some(&id) => Err(LevelLoaderError::UnknownLamp(id)),
+                    None => Ok(()),
+                }
+            }
+            Goal::LampCount(0) => Err(LevelLoaderError::NothingToLight),
+            &Goal::LampCount(count) if count > lamps => {
+                Err(LevelLoaderError::TooFewLamps { count, lamps })
+            }
+            Goal::LampCount(_) => Ok(()),
+            Goal::Flash => {
+                match self
+                    .tiles
+                    .iter()
+                    .any(|tile| matches!(tile, Some(Tile::Capacitor { .. })))
+                {
+                    true => Ok(()),
+                    false => Err(LevelLoaderError::NoCapacitor),
+                }
+            }
+        }
+    }
+}
+
+/// The two sides a tile with two ends connects, `None` for tiles without ends.
+fn ends(tile: &Tile) -> Option<(Side, Side)> {
+    match *tile {
+        Tile::Lamp { entry, exit, .. }
+        | Tile::Cable { entry, exit }
+        | Tile::Switch { entry, exit, .. }
+        | Tile::Resistor { entry, exit, .. }
+        | Tile::Capacitor { entry, exit } => Some((entry, exit)),
+        Tile::Led { anode, cathode, .. } => Some((anode, cathode)),
+        Tile::Battery {
+            plus_side,
+            minus_side,
+        } => Some((plus_side, minus_side)),
+        Tile::TJunction { .. } | Tile::Cross | Tile::Crossover | Tile::P | Tile::N => None,
+    }
+}
+
+/// Id and lit state of every lamp and LED on the board.
+fn lamps(grid: &Grid, analysis: &Analysis) -> Vec<(Option<u8>, bool)> {
+    (0..grid.height())
+        .flat_map(|y| (0..grid.width()).map(move |x| (x, y)))
+        .filter_map(|(x, y)| match grid.get(x, y).unwrap() {
+            Some(Tile::Lamp { id, .. }) => Some((*id, analysis.is_lit((x, y)))),
+            Some(Tile::Led { .. }) => Some((None, analysis.is_lit((x, y)))),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Goal {
+    /// Whether the board meets the goal, `analysis` is its steady state.
+    pub fn is_met(&self, grid: &Grid, analysis: &Analysis) -> bool {
+        let lamps = lamps(grid, analysis);
+
+        match self {
+            Goal::AllLamps => !lamps.is_empty() && lamps.iter().all(|(_, lit)| *lit),
+            Goal::Lamps(ids) => lamps
+                .iter()
+                .all(|(id, lit)| *lit == id.is_some_and(|id| ids.contains(&id))),
+            Goal::LampCount(count) => lamps.iter().filter(|(_, lit)| *lit).count() == *count,
+            Goal::Flash => {
+                // Right after the board is arranged every capacitor is still empty
+                let charging = self::lamps(grid, &circuit::simulate(grid, &Charges::new()));
+                !charging.is_empty()
+                    && charging.iter().all(|(_, lit)| *lit)
+                    && lamps.iter().all(|(_, lit)| !*lit)
+            }
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Goal::AllLamps => "Ziel: alle Lampen leuchten".to_string(),
+            Goal::Lamps(ids) => {
+                let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+                format!("Ziel: nur Lampe {} leuchtet", ids.join(", "))
+            }
+            Goal::LampCount(count) => format!("Ziel: genau {} Lampen leuchten", count),
+            Goal::Flash => "Ziel: alle Lampen blitzen nur kurz auf".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+    Extreme,
+}
+
+#[derive(Debug, Error)]
+pub enum LevelLoaderError {
+    #[error("could not read level file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse level file: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("level is {width}x{height} but has {tiles} tiles")]
+    Size {
+        width: usize,
+        height: usize,
+        tiles: usize,
+    },
+    #[error("level is {width}x{height}, it needs at least one row and column")]
+    NoCells { width: usize, height: usize },
+    #[error("level needs exactly one empty cell but has {0}")]
+    EmptyCells(usize),
+    #[error("tile at ({x}, {y}) has both ends on its {side:?} side")]
+    SameSide { x: usize, y: usize, side: Side },
+    #[error("level has no lamp or LED to light")]
+    NoLamps,
+    #[error("goal has to ask for at least one lamp")]
+    NothingToLight,
+    #[error("goal names lamp {0} but no lamp has that id")]
+    UnknownLamp(u8),
+    #[error("goal asks for {count} lamps but the level has only {lamps}")]
+    TooFewLamps { count: usize, lamps: usize },
+    #[error("goal asks the lamps to flash but the level has no capacitor")]
+    NoCapacitor,
+}
+
+#[derive(Default)]
+pub struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    type Asset = Level;
+    type Settings = ();
+    type Error = LevelLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let level = ron::de::from_bytes::<Level>(&bytes)?;
+        level.validate()?;
+        Ok(level)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.ron"]
+    }
+}
+
+/// Lists the level files in `LEVELS_INDEX`, for the web where the folder can't be read.
+#[derive(Default)]
+pub struct LevelIndexLoader;
+
+impl AssetLoader for LevelIndexLoader {
+    type Asset = LoadedFolder;
+    type Settings = ();
+    type Error = LevelLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let files = ron::de::from_bytes::<Vec<String>>(&bytes)?;
+
+        let handles = files
+            .iter()
+            .map(|file| {
+                load_context
+                    .load::<Level>(format!("{LEVELS_FOLDER}/{file}"))
+                    .untyped()
+            })
+            .collect();
+        Ok(LoadedFolder { handles })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["index.ron"]
+    }
+}
+
+const LEVELS_FOLDER: &str = "levels";
+#[cfg(target_arch = "wasm32")]
+const LEVELS_INDEX: &str = "levels/levels.index.ron";
+
+/// Every level file in `assets/levels`, loaded as one folder at startup. Holding the folder keeps
+/// the levels loaded, the menu lists whatever is in `Assets<Level>`.
+#[derive(Resource)]
+pub struct Levels(#[allow(dead_code)] Handle<LoadedFolder>);
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_levels(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Levels(asset_server.load_folder(LEVELS_FOLDER)));
+}
+
+/// A web server can't list a folder, so the web build reads the level files from an index.
+#[cfg(target_arch = "wasm32")]
+pub fn load_levels(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Levels(asset_server.load(LEVELS_INDEX)));
+}
+
+/// The loaded levels ordered by difficulty and then by name, as the menu shows them.
+pub fn sorted_levels(levels: &Assets<Level>) -> Vec<(AssetId<Level>, &Level)> {
+    let mut levels: Vec<_> = levels.iter().collect();
+    levels.sort_by(|(_, a), (_, b)| (a.difficulty, &a.name).cmp(&(b.difficulty, &b.name)));
+    levels
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    /// Every level file in `assets/levels` with its file name, checked like the loader does.
+    pub fn shipped_levels() -> Vec<(String, Level)> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join(LEVELS_FOLDER);
+        let mut levels: Vec<(String, Level)> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().ends_with(".level.ron"))
+            .map(|path| {
+                let file = path.file_name().unwrap().to_string_lossy().into_owned();
+                let level: Level = ron::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+                if let Err(err) = level.validate() {
+                    panic!("{file}: {err}");
+                }
+                (file, level)
+            })
+            .collect();
+        levels.sort_by(|(a, _), (b, _)| a.cmp(b));
+        levels
+    }
+
+    fn level(width: usize, height: usize, tiles: &str) -> Level {
+        Level {
+            name: "Test".to_string(),
+            difficulty: Difficulty::Easy,
+            width,
+            height,
+            tiles: ron::from_str(tiles).unwrap(),
+            goal: Goal::AllLamps,
+            count_mistakes: false,
+        }
+    }
+
+    /// Two lamps with the ids 1 and 2 and the empty cell.
+    fn two_lamps(goal: Goal) -> Level {
+        Level {
+            goal,
+            ..level(
+                3,
+                1,
+                "[
+                    Some(Lamp(entry: Left, exit: Right, id: Some(1))),
+                    Some(Lamp(entry: Left, exit: Right, id: Some(2))),
+                    None,
+                ]",
+            )
+        }
+    }
+
+    #[test]
+    fn index_lists_every_level() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join("levels/levels.index.ron");
+        let mut files: Vec<String> = ron::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        files.sort();
+
+        let levels: Vec<String> = shipped_levels().into_iter().map(|(file, _)| file).collect();
+        assert_eq!(files, levels);
+    }
+
+    #[test]
+    fn rejects_tile_with_both_ends_on_one_side() {
+        let level = level(2, 1, "[Some(Cable(entry: Left, exit: Left)), None]");
+        assert!(matches!(
+            level.validate(),
+            Err(LevelLoaderError::SameSide {
+                x: 0,
+                y: 0,
+                side: Side::Left
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_level_without_cells() {
+        let level = level(0, 0, "[]");
+        assert!(matches!(
+            level.validate(),
+            Err(LevelLoaderError::NoCells { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_wrong_number_of_empty_cells() {
+        let none_empty = level(2, 1, "[Some(P), Some(N)]");
+        assert!(matches!(
+            none_empty.validate(),
+            Err(LevelLoaderError::EmptyCells(0))
+        ));
+
+        let two_empty = level(3, 1, "[Some(P), None, None]");
+        assert!(matches!(
+            two_empty.validate(),
+            Err(LevelLoaderError::EmptyCells(2))
+        ));
+    }
+
+    #[test]
+    fn accepts_goals_that_can_be_met() {
+        assert!(two_lamps(Goal::AllLamps).validate().is_ok());
+        assert!(two_lamps(Goal::Lamps(vec![2])).validate().is_ok());
+        assert!(two_lamps(Goal::LampCount(2)).validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_goals_that_ask_for_nothing() {
+        assert!(matches!(
+            two_lamps(Goal::Lamps(vec![])).validate(),
+            Err(LevelLoaderError::NothingToLight)
+        ));
+        assert!(matches!(
+            two_lamps(Goal::LampCount(0)).validate(),
+            Err(LevelLoaderError::NothingToLight)
+        ));
+    }
+
+    #[test]
+    fn rejects_goals_that_cannot_be_met() {
+        assert!(matches!(
+            two_lamps(Goal::Lamps(vec![1, 3])).validate(),
+            Err(LevelLoaderError::UnknownLamp(3))
+        ));
+        assert!(matches!(
+            two_lamps(Goal::LampCount(3)).validate(),
+            Err(LevelLoaderError::TooFewLamps { count: 3, lamps: 2 })
+        ));
+
+        assert!(matches!(
+            two_lamps(Goal::Flash).validate(),
+            Err(LevelLoaderError::NoCapacitor)
+        ));
+
+        let no_lamps = level(2, 1, "[Some(P), None]");
+        assert!(matches!(
+            no_lamps.validate(),
+            Err(LevelLoaderError::NoLamps)
+        ));
+    }
+}
@@ -1,28 +1,66 @@
+mod carriers;
+mod circuit;
+mod flow;
+mod gamepad;
+mod hud;
+mod level;
+mod solver;
+
+use crate::carriers::{DepletionZones, move_carriers, spawn_carriers};
+use crate::circuit::{Analysis, Charges};
+use crate::flow::{
+    CurrentConvention, CurrentFlow, draw_current_flow, toggle_convention_listener,
+    update_current_flow,
+};
+use crate::gamepad::{
+    GamepadCursor, MenuFocus, draw_gamepad_cursor, navigate_menu, play_with_gamepad,
+};
+use crate::hud::{
+    HudText, PuzzleSolved, PuzzleStats, ShortCircuit, count_mistake, count_move, finish_puzzle,
+    hud_node, load_records, reset_puzzle_stats, spawn_hud, update_hud,
+};
+use crate::level::{Goal, Level, LevelIndexLoader, LevelLoader, load_levels, sorted_levels};
 use bevy::audio::Volume;
 use bevy::core_pipeline::tonemapping::{DebandDither, Tonemapping};
+use bevy::ecs::system::IntoObserverSystem;
 use bevy::math::ops::round;
 use bevy::post_process::bloom::{Bloom, BloomCompositeMode, BloomPrefilter};
 use bevy::prelude::*;
 use bevy::sprite::Anchor;
-use bevy::window::WindowResolution;
-use rand::prelude::SliceRandom;
+use bevy::tasks::futures::check_ready;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use bevy::window::{PrimaryWindow, WindowResizeConstraints, WindowResized, WindowResolution};
 use rand::rng;
+use rand::seq::IndexedRandom;
+use serde::Deserialize;
 use std::cmp::min;
-use std::collections::HashSet;
+use std::collections::HashMap;
 
 const WIN_TITLE: &str = "Halbleiter";
 
+/// Size the window opens with, it can be resized freely down to `MIN_WIN_SIZE` in both directions.
 const WIN_WIDTH: u32 = 1500;
 const WIN_HEIGHT: u32 = 720;
+const MIN_WIN_SIZE: f32 = 400.0;
+
+/// Room above and below the board on a portrait screen, for the buttons and the HUD.
+const PORTRAIT_UI_HEIGHT: u32 = 220;
+/// Smallest tile size in pixels, the board rather overflows a tiny window than disappears.
+const MIN_TILE_SIZE: f32 = 16.0;
 
 const BACKGROUND_COLOR: Color = Color::srgb(0.1, 0.1, 0.1);
+const HINT_COLOR: Color = Color::srgb(0.2, 1.0, 0.4);
 
 fn main() {
     let window = WindowPlugin {
         primary_window: Some(Window {
             title: WIN_TITLE.to_string(),
             resolution: WindowResolution::new(WIN_WIDTH, WIN_HEIGHT),
-            resizable: false,
+            resize_constraints: WindowResizeConstraints {
+                min_width: MIN_WIN_SIZE,
+                min_height: MIN_WIN_SIZE,
+                ..default()
+            },
             ..default()
         }),
         ..default()
@@ -35,26 +73,70 @@ fn main() {
                 .set(ImagePlugin::default_nearest()),
         )
         .insert_resource(ClearColor(BACKGROUND_COLOR))
+        .init_asset::<Level>()
+        .init_asset_loader::<LevelLoader>()
+        .init_asset_loader::<LevelIndexLoader>()
         .init_state::<AppState>()
         .init_state::<LevelState>()
-        .add_systems(Startup, setup_camera)
+        .init_resource::<CurrentConvention>()
+        .init_resource::<TileDragSystemState>()
+        .add_systems(Startup, (setup_camera, load_levels, load_records))
         // Intro Systems
         .add_systems(OnEnter(AppState::Intro), spawn_intro)
         .add_systems(OnExit(AppState::Intro), cleanup_intro)
         // Menu Systems
         .add_systems(OnEnter(AppState::Menu), spawn_menu)
         .add_systems(OnExit(AppState::Menu), cleanup_menu)
+        .add_systems(Update, refresh_menu.run_if(in_state(AppState::Menu)))
+        .add_systems(Update, navigate_menu.run_if(resource_exists::<MenuFocus>))
         // Game Systems
-        .add_systems(OnEnter(AppState::Game), setup.chain())
+        .add_systems(OnEnter(AppState::Game), (setup, spawn_hud).chain())
         .add_systems(
             Update,
-            (tile_drag_system, restart_listener).run_if(in_state(AppState::Game)),
+            (
+                (
+                    tile_drag_system,
+                    slide_tiles,
+                    poll_hint,
+                    draw_hint,
+                    slide_listener,
+                    play_with_gamepad,
+                    draw_gamepad_cursor,
+                    move_carriers,
+                    relayout_board,
+                    (
+                        simulate_circuit,
+                        light_lamps.run_if(resource_exists::<CircuitState>),
+                    )
+                        .chain(),
+                )
+                    .run_if(resource_exists::<Grid>),
+                update_hud.run_if(resource_exists::<PuzzleStats>),
+                relayout_ui,
+                fade_short_circuit_flash,
+                draw_current_flow.run_if(resource_exists::<CurrentFlow>),
+                toggle_convention_listener,
+                restart_listener,
+                undo_listener,
+            )
+                .run_if(in_state(AppState::Game)),
         )
         .add_systems(OnEnter(AppState::Game), |mut commands: Commands| {
             commands.trigger(MakeNewPuzzleRequest)
         })
         .add_systems(OnExit(AppState::Game), cleanup_puzzle)
         .add_observer(new_puzzle)
+        .add_observer(move_tile)
+        .add_observer(request_hint)
+        .add_observer(check_short_circuit)
+        .add_observer(check_solved)
+        .add_observer(show_depletion_layers)
+        .add_observer(update_current_flow)
+        .add_observer(reset_puzzle_stats)
+        .add_observer(count_move)
+        .add_observer(finish_puzzle)
+        .add_observer(show_short_circuit)
+        .add_observer(count_mistake)
         .run();
 }
 
@@ -70,9 +152,18 @@ enum AppState {
 enum LevelState {
     #[default]
     Menu,
-    Easy,
-    Medium,
-    Hard,
+    /// Playing the level with this id, picked from the menu.
+    Level(AssetId<Level>),
+}
+
+impl LevelState {
+    /// The level being played, `None` in the menu.
+    fn level<'a>(&self, levels: &'a Assets<Level>) -> Option<&'a Level> {
+        match self {
+            LevelState::Menu => None,
+            LevelState::Level(id) => levels.get(*id),
+        }
+    }
 }
 
 fn setup_camera(mut commands: Commands) {
@@ -87,6 +178,30 @@ fn setup_camera(mut commands: Commands) {
 #[require(Node, BackgroundColor)]
 struct IntroRoot;
 
+/// Explains the controls on top of the intro, closed with its own button.
+#[derive(Component)]
+struct HelpOverlay;
+
+const HELP_TEXT: &str = "Steuerung
+
+Ziehe die Teile mit der Maus oder dem Finger in das freie Feld
+oder tippe ein Teil an, um es hineinzuschieben.
+Die Pfeiltasten (oder WASD) schieben ein Teil in das freie Feld.
+Ein Schalter wird durch Anklicken umgeschaltet.
+Strg+Z macht einen Zug rueckgaengig, Strg+Umschalt+Z wiederholt ihn.
+R beginnt neu, C wechselt die Stromrichtung.
+
+Mit dem Controller:
+ - Steuerkreuz: Teil waehlen
+ - A: Teil schieben, X: Schalter umschalten
+ - Y: Tipp, B: Stromrichtung wechseln
+ - LT und RT: rueckgaengig und wiederholen
+ - Start: neu beginnen, Select: zurueck zum Menu
+
+Ein Kondensator speichert Ladung, eine Lampe leuchtet damit noch kurz weiter.
+An der Sperrschicht verbinden sich Loecher und Elektronen, dort bleibt sie leer:
+breit, wenn sie sperrt, schmal, wenn sie durchlaesst.";
+
 fn spawn_intro(mut commands: Commands) {
     let root = commands
         .spawn((
@@ -109,13 +224,13 @@ fn spawn_intro(mut commands: Commands) {
                 "In diesem Spiel musst du einen einfachen Stromkreis zusammenbauen.
 Dabei benutzt du ein besonderes Bauteil aus zwei Teilen: p-dotiert und n-dotiert.
 Es gibt folgende Teile:
- - Stromquelle
- - Kabel
- - Lampe
+ - Stromquelle, Kabel, Lampe, Leuchtdiode (LED), Widerstand und Kondensator
+ - Schalter, Verzweigung, Kreuzung und Ueberkreuzung
  - p- und n-dotiertes Teil
 
 Wenn p- und n-Teil zusammenkommen, entsteht zwischen ihnen eine Sperrschicht.
 Diese Sperrschicht kann den Strom blockieren oder durchlassen.
+Im p-Teil wandern Loecher (rot), im n-Teil freie Elektronen (blau).
 
 Du sollst die beiden Teile richtig herum in den Stromkreis einbauen und die Lampe zum Leuchten bringen.
 Bringe Licht ins dunkle!",
@@ -128,16 +243,92 @@ Bringe Licht ins dunkle!",
         ))
         .id();
 
-    let next_button_container = commands
+    let buttons_container = commands
         .spawn(Node {
             position_type: PositionType::Absolute,
             bottom: Val::Px(50.0),
             right: Val::Px(50.0),
+            column_gap: Val::Px(20.0),
             ..default()
         })
         .id();
 
-    let next_button = commands
+    let next_button = spawn_intro_button(
+        &mut commands,
+        "Next",
+        |_: On<Press>, mut next_state: ResMut<NextState<AppState>>| {
+            next_state.set(AppState::Menu);
+        },
+    );
+    let help_button = spawn_intro_button(
+        &mut commands,
+        "Hilfe",
+        move |press: On<Press>, mut commands: Commands| {
+            spawn_help(&mut commands, root, vec![next_button, press.entity]);
+        },
+    );
+
+    commands
+        .entity(buttons_container)
+        .add_children(&[help_button, next_button]);
+    commands
+        .entity(root)
+        .add_children(&[text, buttons_container]);
+
+    commands.insert_resource(MenuFocus::new(vec![next_button, help_button]));
+}
+
+/// Covers the intro with the controls, closing it gives the gamepad focus back to `return_focus`.
+fn spawn_help(commands: &mut Commands, root: Entity, return_focus: Vec<Entity>) {
+    let overlay = commands
+        .spawn((
+            HelpOverlay,
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(20.0),
+                ..default()
+            },
+            BackgroundColor(Color::srgb(0.1, 0.1, 0.1)),
+        ))
+        .id();
+
+    let text = commands
+        .spawn((
+            Text::new(HELP_TEXT),
+            TextFont {
+                font_size: 20.0,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+        ))
+        .id();
+
+    let close_button = spawn_intro_button(
+        commands,
+        "Zurueck",
+        move |_: On<Press>, mut commands: Commands| {
+            commands.entity(overlay).despawn();
+            commands.insert_resource(MenuFocus::new(return_focus.clone()));
+        },
+    );
+
+    commands.entity(overlay).add_children(&[text, close_button]);
+    commands.entity(root).add_child(overlay);
+
+    commands.insert_resource(MenuFocus::new(vec![close_button]));
+}
+
+fn spawn_intro_button<B: Bundle, M>(
+    commands: &mut Commands,
+    label: &str,
+    on_press: impl IntoObserverSystem<Press, B, M>,
+) -> Entity {
+    commands
         .spawn((
             Button,
             Node {
@@ -152,14 +343,11 @@ Bringe Licht ins dunkle!",
             BorderColor::all(Color::BLACK),
             BorderRadius::all(Val::Px(10.0)),
         ))
-        .observe(
-            |_: On<Pointer<Click>>, mut next_state: ResMut<NextState<AppState>>| {
-                next_state.set(AppState::Menu);
-            },
-        )
+        .observe(press_on_click)
+        .observe(on_press)
         .with_children(|parent| {
             parent.spawn((
-                Text::new("Next"),
+                Text::new(label),
                 TextFont {
                     font_size: 20.0,
                     ..default()
@@ -167,20 +355,14 @@ Bringe Licht ins dunkle!",
                 TextColor(Color::WHITE),
             ));
         })
-        .id();
-
-    commands
-        .entity(next_button_container)
-        .add_child(next_button);
-    commands
-        .entity(root)
-        .add_children(&[text, next_button_container]);
+        .id()
 }
 
 fn cleanup_intro(mut commands: Commands, query: Query<Entity, With<IntroRoot>>) {
     for entity in query.iter() {
         commands.entity(entity).despawn();
     }
+    commands.remove_resource::<MenuFocus>();
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -191,14 +373,23 @@ fn cleanup_intro(mut commands: Commands, query: Query<Entity, With<IntroRoot>>)
 #[require(Node, BackgroundColor)]
 struct MenuRoot;
 
-fn spawn_button(
+/// A button of the intro or the menu was pressed, with the mouse, a finger or a gamepad.
+#[derive(EntityEvent)]
+struct Press {
+    entity: Entity,
+}
+
+/// Presses the button that was clicked or tapped.
+fn press_on_click(click: On<Pointer<Click>>, mut commands: Commands) {
+    commands.trigger(Press {
+        entity: click.entity,
+    });
+}
+
+fn spawn_button<B: Bundle, M>(
     commands: &mut Commands,
     label: &str,
-    on_click: fn(
-        _trigger: On<Pointer<Click>>,
-        next_app_state: ResMut<NextState<AppState>>,
-        next_level_state: ResMut<NextState<LevelState>>,
-    ),
+    on_press: impl IntoObserverSystem<Press, B, M>,
 ) -> Entity {
     commands
         .spawn((
@@ -215,7 +406,8 @@ fn spawn_button(
             BorderColor::all(Color::BLACK),
             BorderRadius::all(Val::Px(10.0)),
         ))
-        .observe(on_click)
+        .observe(press_on_click)
+        .observe(on_press)
         .with_children(|parent| {
             parent.spawn((
                 Text::new(label),
@@ -229,7 +421,29 @@ fn spawn_button(
         .id()
 }
 
-fn spawn_menu(mut commands: Commands) {
+fn spawn_menu(mut commands: Commands, levels: Res<Assets<Level>>) {
+    build_menu(&mut commands, &levels);
+}
+
+/// Lists levels that finished loading while the menu is open.
+fn refresh_menu(
+    mut events: MessageReader<AssetEvent<Level>>,
+    mut commands: Commands,
+    roots: Query<Entity, With<MenuRoot>>,
+    levels: Res<Assets<Level>>,
+) {
+    if events.read().count() == 0 {
+        return;
+    }
+
+    for entity in roots.iter() {
+        commands.entity(entity).despawn();
+    }
+    build_menu(&mut commands, &levels);
+}
+
+/// Shows a row of buttons for every difficulty, one button per level.
+fn build_menu(commands: &mut Commands, levels: &Assets<Level>) {
     let root = commands
         .spawn((
             MenuRoot,
@@ -257,45 +471,59 @@ fn spawn_menu(mut commands: Commands) {
             TextColor(Color::WHITE),
         ))
         .id();
+    commands.entity(root).add_child(title);
+
+    // LEVEL BUTTONS
+    let mut buttons = Vec::new();
+    let mut row = None;
+    let mut row_difficulty = None;
+    for (id, level) in sorted_levels(levels) {
+        if row_difficulty != Some(level.difficulty) {
+            let label = commands
+                .spawn((
+                    Text::new(format!("{:?}", level.difficulty).to_uppercase()),
+                    TextFont {
+                        font_size: 30.0,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                    Node {
+                        width: Val::Px(150.0),
+                        ..default()
+                    },
+                ))
+                .id();
+            let new_row = commands
+                .spawn(Node {
+                    flex_direction: FlexDirection::Row,
+                    flex_wrap: FlexWrap::Wrap,
+                    align_items: AlignItems::Center,
+                    column_gap: Val::Px(20.0),
+                    row_gap: Val::Px(10.0),
+                    ..default()
+                })
+                .add_child(label)
+                .id();
+            commands.entity(root).add_child(new_row);
+            row = Some(new_row);
+            row_difficulty = Some(level.difficulty);
+        }
 
-    // LEVEL EASY BUTTON
-    let easy_button = spawn_button(
-        &mut commands,
-        "EASY",
-        |_trigger: On<Pointer<Click>>,
-         mut next_app_state: ResMut<NextState<AppState>>,
-         mut next_level_state: ResMut<NextState<LevelState>>| {
-            info!("Easy button clicked!");
-            next_app_state.set(AppState::Game);
-            next_level_state.set(LevelState::Easy);
-        },
-    );
-
-    // LEVEL MEDIUM BUTTON
-    let medium_button = spawn_button(
-        &mut commands,
-        "MEDIUM",
-        |_trigger: On<Pointer<Click>>,
-         mut next_app_state: ResMut<NextState<AppState>>,
-         mut next_level_state: ResMut<NextState<LevelState>>| {
-            info!("Medium button clicked!");
-            next_app_state.set(AppState::Game);
-            next_level_state.set(LevelState::Medium);
-        },
-    );
-
-    // LEVEL HARD BUTTON
-    let hard_button = spawn_button(
-        &mut commands,
-        "HARD",
-        |_trigger: On<Pointer<Click>>,
-         mut next_app_state: ResMut<NextState<AppState>>,
-         mut next_level_state: ResMut<NextState<LevelState>>| {
-            info!("Hard button clicked!");
-            next_app_state.set(AppState::Game);
-            next_level_state.set(LevelState::Hard);
-        },
-    );
+        let name = level.name.clone();
+        let button = spawn_button(
+            commands,
+            &level.name,
+            move |_trigger: On<Press>,
+                  mut next_app_state: ResMut<NextState<AppState>>,
+                  mut next_level_state: ResMut<NextState<LevelState>>| {
+                info!("{} button clicked!", name);
+                next_app_state.set(AppState::Game);
+                next_level_state.set(LevelState::Level(id));
+            },
+        );
+        commands.entity(row.unwrap()).add_child(button);
+        buttons.push(button);
+    }
 
     // Quit Button
     // let quit_button = commands
@@ -327,13 +555,9 @@ fn spawn_menu(mut commands: Commands) {
     //     .id();
 
     // Build screen hierarchy
-    commands.entity(root).add_children(&[
-        title,
-        easy_button,
-        medium_button,
-        hard_button,
-        // quit_button,
-    ]);
+    // commands.entity(root).add_child(quit_button);
+
+    commands.insert_resource(MenuFocus::new(buttons));
 }
 
 // 4. Cleanup System
@@ -341,6 +565,7 @@ fn cleanup_menu(mut commands: Commands, query: Query<Entity, With<MenuRoot>>) {
     for entity in query.iter() {
         commands.entity(entity).despawn();
     }
+    commands.remove_resource::<MenuFocus>();
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -352,6 +577,7 @@ struct Sounds {
     start_drag: Handle<AudioSource>,
     lamp_turns_on: Handle<AudioSource>,
     misdrop: Handle<AudioSource>,
+    spark: Handle<AudioSource>,
 }
 
 #[derive(Component)]
@@ -364,7 +590,21 @@ struct TileComponent {
 #[derive(Component)]
 struct GridLine;
 
-#[derive(Copy, Clone, Debug)]
+/// Text drawn on a tile, placed and sized in tiles so it can follow the tile size.
+#[derive(Component)]
+struct TileLabel {
+    /// From the top left corner of the tile, y grows downwards.
+    offset: Vec2,
+    font_size: f32,
+}
+
+impl TileLabel {
+    fn transform(&self, tile_size: f32) -> Transform {
+        Transform::from_xyz(self.offset.x * tile_size, -self.offset.y * tile_size, 1.0)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
 enum Side {
     Left,
     Right,
@@ -389,105 +629,152 @@ impl Side {
             Side::Top => -1,
         }
     }
+
+    fn opposite(&self) -> Side {
+        match self {
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+            Side::Bottom => Side::Top,
+            Side::Top => Side::Bottom,
+        }
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
 enum Tile {
-    Lamp { entry: Side, exit: Side },
-    Battery { plus_side: Side, minus_side: Side },
-    Cable { entry: Side, exit: Side },
+    Lamp {
+        entry: Side,
+        exit: Side,
+        /// Lets a level's goal refer to this lamp, shown as a number on the tile.
+        #[serde(default)]
+        id: Option<u8>,
+    },
+    /// Only glows while current flows in through the anode.
+    Led {
+        anode: Side,
+        cathode: Side,
+        color: LedColor,
+    },
+    Battery {
+        plus_side: Side,
+        minus_side: Side,
+    },
+    Cable {
+        entry: Side,
+        exit: Side,
+    },
+    /// Conducts like a cable while closed, breaks the circuit while open. Clicking it toggles it.
+    Switch {
+        entry: Side,
+        exit: Side,
+        closed: bool,
+    },
+    /// Limits the current, the value is shown on the tile.
+    Resistor {
+        entry: Side,
+        exit: Side,
+        ohms: u32,
+    },
+    /// Charges while current flows through it and gives the charge back once the battery is gone.
+    Capacitor {
+        entry: Side,
+        exit: Side,
+    },
+    /// Connects every side except `missing`.
+    TJunction {
+        missing: Side,
+    },
+    /// Connects all four sides.
+    Cross,
+    /// Connects left with right and top with bottom, the two wires pass each other.
+    Crossover,
     P,
     N,
 }
 
-#[derive(Resource, Clone, Debug)]
-struct Grid(grid::Grid<Option<Tile>>);
-impl Grid {
-    fn width(&self) -> usize {
-        self.0.cols()
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+enum LedColor {
+    Red,
+    Green,
+    Blue,
+    Yellow,
+}
+impl LedColor {
+    fn base(&self) -> Color {
+        match self {
+            LedColor::Red => Color::srgb(1.0, 0.1, 0.1),
+            LedColor::Green => Color::srgb(0.1, 1.0, 0.2),
+            LedColor::Blue => Color::srgb(0.2, 0.4, 1.0),
+            LedColor::Yellow => Color::srgb(1.0, 1.0, 0.0),
+        }
     }
 
-    fn height(&self) -> usize {
-        self.0.rows()
+    /// Tint of a dark LED, so its color can be seen before it glows.
+    fn dark(&self) -> Color {
+        self.base().darker(0.4)
     }
 
-    fn get(&self, x: usize, y: usize) -> Option<&Option<Tile>> {
-        self.0.get(y, x)
+    /// HDR tint of a lit LED, brighter with more current just like [`lamp_color`].
+    fn glow(&self, brightness: f32) -> Color {
+        let glow = 1.0 + 4.0 * brightness.min(MAX_LAMP_BRIGHTNESS);
+        let color = self.base().to_srgba();
+        Color::srgb(color.red * glow, color.green * glow, color.blue * glow)
     }
+}
 
-    fn swap(&mut self, (x1, y1): (usize, usize), (x2, y2): (usize, usize)) {
-        self.0.swap((y1, x1), (y2, x2));
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Move {
+    /// Slides the tile at `from` into the empty cell at `to`.
+    Slide {
+        from: (usize, usize),
+        to: (usize, usize),
+    },
+    /// Opens or closes the switch at `cell`.
+    Toggle { cell: (usize, usize) },
+}
+impl Move {
+    fn reversed(&self) -> Move {
+        match *self {
+            Move::Slide { from, to } => Move::Slide { from: to, to: from },
+            Move::Toggle { cell } => Move::Toggle { cell },
+        }
     }
+}
 
-    fn tile_size(&self) -> f32 {
-        min(
-            WIN_WIDTH / self.width() as u32,
-            WIN_HEIGHT / self.height() as u32,
-        ) as f32
+#[derive(Resource, Clone, Debug, PartialEq, Eq, Hash)]
+struct Grid {
+    tiles: grid::Grid<Option<Tile>>,
+    goal: Goal,
+}
+impl Grid {
+    /// The level's board in its solved layout.
+    fn new(level: &Level) -> Grid {
+        Grid {
+            tiles: grid::Grid::from_vec(level.tiles.clone(), level.width),
+            goal: level.goal.clone(),
+        }
     }
 
-    fn start_x(&self) -> f32 {
-        -(self.width() as f32) / 2.0 * self.tile_size()
+    fn width(&self) -> usize {
+        self.tiles.cols()
     }
 
-    fn start_y(&self) -> f32 {
-        (self.height() as f32) / 2.0 * self.tile_size()
+    fn height(&self) -> usize {
+        self.tiles.rows()
     }
 
-    fn world_pos_from_xy(&self, x: usize, y: usize) -> Option<Vec2> {
-        if x >= self.width() || y >= self.height() {
-            return None;
-        }
-
-        Some(Vec2::new(
-            self.start_x() + x as f32 * self.tile_size(),
-            self.start_y() - y as f32 * self.tile_size(),
-        ))
+    fn get(&self, x: usize, y: usize) -> Option<&Option<Tile>> {
+        self.tiles.get(y, x)
     }
 
-    fn xy_from_world_pos(&self, pos: Vec2) -> (usize, usize) {
-        let mut normalized = (pos - Vec2::new(self.start_x(), self.start_y())) / self.tile_size();
-
-        normalized.x = round(normalized.x);
-        normalized.y = round(normalized.y);
-
-        if normalized.x < 0.0 {
-            normalized.x = 0.0;
-        }
-        if normalized.y > 0.0 {
-            normalized.y = 0.0;
-        }
-        if normalized.x >= self.width() as f32 {
-            normalized.x = self.width() as f32 - 1.0;
-        }
-        if normalized.y <= -(self.height() as f32) {
-            normalized.y = self.height() as f32 + 1.0;
-        }
-
-        (normalized.x as usize, -normalized.y as usize)
+    fn neighbour(&self, (x, y): (usize, usize), side: Side) -> Option<&Tile> {
+        let x = x.checked_add_signed(side.x_offset() as isize)?;
+        let y = y.checked_add_signed(side.y_offset() as isize)?;
+        self.get(x, y)?.as_ref()
     }
 
-    fn snap_to_grid(&self, pos: Vec2) -> Vec2 {
-        let start = Vec2::new(self.start_x(), self.start_y());
-
-        let mut normalized = (pos - start) / self.tile_size();
-        normalized.x = round(normalized.x);
-        normalized.y = round(normalized.y);
-
-        if normalized.x < 0.0 {
-            normalized.x = 0.0;
-        }
-        if normalized.y > 0.0 {
-            normalized.y = 0.0;
-        }
-        if normalized.x >= self.width() as f32 {
-            normalized.x = self.width() as f32 - 1.0;
-        }
-        if normalized.y <= -(self.height() as f32) {
-            normalized.y = self.height() as f32 + 1.0;
-        }
-
-        normalized * self.tile_size() + start
+    fn swap(&mut self, (x1, y1): (usize, usize), (x2, y2): (usize, usize)) {
+        self.tiles.swap((y1, x1), (y2, x2));
     }
 
     fn has_unobstructed_path(&self, (x1, y1): (usize, usize), (x2, y2): (usize, usize)) -> bool {
@@ -543,209 +830,250 @@ impl Grid {
         false
     }
 
-    fn is_solved_helper(
-        &self,
-        start_x: i32,
-        start_y: i32,
-        prev_x: i32,
-        prev_y: i32,
-        prev_tile: Tile,
-        found_lamp_init: bool,
-    ) -> bool {
-        #[derive(Hash, Eq, PartialEq, Copy, Clone)]
-        struct StateKey {
-            x: i32,
-            y: i32,
-            prev_x: i32,
-            prev_y: i32,
-            prev_kind: u8, // tile type compressed
-            found_lamp: bool,
-        }
+    fn empty_cell(&self) -> Option<(usize, usize)> {
+        (0..self.height())
+            .flat_map(|y| (0..self.width()).map(move |x| (x, y)))
+            .find(|&(x, y)| self.get(x, y) == Some(&None))
+    }
 
-        fn tile_kind(t: Tile) -> u8 {
-            match t {
-                Tile::Battery { .. } => 0,
-                Tile::Lamp { .. } => 1,
-                Tile::Cable { .. } => 2,
-                Tile::P => 3,
-                Tile::N => 4,
+    fn is_legal_move(&self, mv: Move) -> bool {
+        match mv {
+            Move::Slide { from, to } => {
+                let (Some(Some(_)), Some(None)) = (self.get(from.0, from.1), self.get(to.0, to.1))
+                else {
+                    return false;
+                };
+                self.has_unobstructed_path(to, from)
+            }
+            Move::Toggle { cell } => {
+                matches!(self.get(cell.0, cell.1), Some(Some(Tile::Switch { .. })))
             }
         }
+    }
 
-        let mut visited: HashSet<StateKey> = HashSet::new();
-
-        let mut stack = vec![(start_x, start_y, prev_x, prev_y, prev_tile, found_lamp_init)];
-
-        while let Some((x, y, prev_x, prev_y, prev_tile, found_lamp)) = stack.pop() {
-            // State key
-            let key = StateKey {
-                x,
-                y,
-                prev_x,
-                prev_y,
-                prev_kind: tile_kind(prev_tile),
-                found_lamp,
-            };
-
-            // Skip if visited
-            if !visited.insert(key) {
-                continue;
+    fn legal_moves(&self) -> Vec<Move> {
+        let cells: Vec<(usize, usize)> = (0..self.height())
+            .flat_map(|y| (0..self.width()).map(move |x| (x, y)))
+            .collect();
+
+        let mut moves = Vec::new();
+        for &to in cells.iter() {
+            for &from in cells.iter() {
+                let mv = Move::Slide { from, to };
+                if self.is_legal_move(mv) {
+                    moves.push(mv);
+                }
             }
+        }
+        for &cell in cells.iter() {
+            let mv = Move::Toggle { cell };
+            if self.is_legal_move(mv) {
+                moves.push(mv);
+            }
+        }
+        moves
+    }
 
-            // Bounds
-            if x < 0 || x >= self.width() as i32 || y < 0 || y >= self.height() as i32 {
-                continue;
+    fn apply(&mut self, mv: Move) {
+        match mv {
+            Move::Slide { from, to } => self.swap(from, to),
+            Move::Toggle { cell: (x, y) } => {
+                if let Some(Some(Tile::Switch { closed, .. })) = self.tiles.get_mut(y, x) {
+                    *closed = !*closed;
+                }
             }
+        }
+    }
 
-            let tile = match self.get(x as usize, y as usize).unwrap() {
-                Some(t) => t,
-                None => continue,
+    /// Scrambles the board with random legal moves, so the result can always be moved back.
+    /// Keeps going past `SCRAMBLE_MOVES` for as long as the board is still solved.
+    fn scramble(&mut self, rng: &mut impl rand::Rng) {
+        let mut last: Option<Move> = None;
+        let mut steps = 0;
+
+        while steps < SCRAMBLE_MOVES || self.is_solved() {
+            // Don't undo the previous move unless it is the only one
+            let moves = self.legal_moves();
+            let forward: Vec<Move> = moves
+                .iter()
+                .copied()
+                .filter(|mv| last.map(|last| last.reversed()) != Some(*mv))
+                .collect();
+            let candidates = if forward.is_empty() { &moves } else { &forward };
+
+            let Some(&mv) = candidates.choose(rng) else {
+                warn!("Board has no legal moves, cannot scramble it");
+                break;
             };
+            self.apply(mv);
+            last = Some(mv);
+            steps += 1;
+        }
+    }
 
-            match tile {
-                Tile::Lamp { entry, exit } => {
-                    if (x + entry.x_offset(), y + entry.y_offset()) != (prev_x, prev_y) {
-                        continue;
-                    }
-                    stack.push((x + exit.x_offset(), y + exit.y_offset(), x, y, *tile, true));
-                }
+    /// Whether the goal is met in the steady state, once every capacitor is charged, and for
+    /// `Goal::Flash` also right after the board is arranged. Winning, the scrambler and the solver
+    /// all go by this.
+    fn is_solved(&self) -> bool {
+        self.goal.is_met(self, &circuit::analyze(self))
+    }
+}
 
-                Tile::Cable { entry, exit } => {
-                    if (x + entry.x_offset(), y + entry.y_offset()) != (prev_x, prev_y) {
-                        continue;
-                    }
-                    stack.push((
-                        x + exit.x_offset(),
-                        y + exit.y_offset(),
-                        x,
-                        y,
-                        *tile,
-                        found_lamp,
-                    ));
-                }
+/// Where the board is drawn: centred in the window, with tiles as large as fit.
+#[derive(Resource, Clone, Copy, Debug)]
+struct BoardLayout {
+    /// Size of the window in logical pixels, the board is laid out to fill it. Logical pixels are
+    /// also the unit of the 2D camera and the cursor, so the layout works on HiDPI screens too.
+    screen: UVec2,
+    width: usize,
+    height: usize,
+}
+impl BoardLayout {
+    fn new(grid: &Grid, screen: UVec2) -> BoardLayout {
+        BoardLayout {
+            screen,
+            width: grid.width(),
+            height: grid.height(),
+        }
+    }
 
-                Tile::Battery {
-                    plus_side: _,
-                    minus_side,
-                } => {
-                    if (x + minus_side.x_offset(), y + minus_side.y_offset()) != (prev_x, prev_y) {
-                        continue;
-                    }
-                    if found_lamp {
-                        return true;
-                    }
-                }
+    fn tile_size(&self) -> f32 {
+        let mut area = self.screen;
+        if is_portrait(self.screen) {
+            area.y = area.y.saturating_sub(2 * PORTRAIT_UI_HEIGHT);
+        }
 
-                Tile::P => {
-                    for side in [Side::Left, Side::Right, Side::Top, Side::Bottom] {
-                        let nx = x + side.x_offset();
-                        let ny = y + side.y_offset();
+        let size = min(area.x / self.width as u32, area.y / self.height as u32) as f32;
+        size.max(MIN_TILE_SIZE)
+    }
 
-                        if nx < 0 || ny < 0 {
-                            continue;
-                        }
+    fn start_x(&self) -> f32 {
+        -(self.width as f32) / 2.0 * self.tile_size()
+    }
 
-                        if let Some(Some(Tile::N)) = self.get(nx as usize, ny as usize) {
-                            stack.push((nx, ny, x, y, *tile, found_lamp));
-                        }
-                    }
-                }
+    fn start_y(&self) -> f32 {
+        (self.height as f32) / 2.0 * self.tile_size()
+    }
 
-                Tile::N => {
-                    if !matches!(prev_tile, Tile::P) {
-                        continue;
-                    }
-                    for side in [Side::Left, Side::Right, Side::Top, Side::Bottom] {
-                        let nx = x + side.x_offset();
-                        let ny = y + side.y_offset();
-                        stack.push((nx, ny, x, y, *tile, found_lamp));
-                    }
-                }
-            }
+    fn world_pos_from_xy(&self, x: usize, y: usize) -> Option<Vec2> {
+        if x >= self.width || y >= self.height {
+            return None;
         }
 
-        false
+        Some(Vec2::new(
+            self.start_x() + x as f32 * self.tile_size(),
+            self.start_y() - y as f32 * self.tile_size(),
+        ))
     }
 
-    fn is_solved(&self) -> bool {
-        let mut found_battery = false;
-        let (mut battery_x, mut battery_y, mut plus_side, mut minus_side) =
-            (0, 0, Side::Left, Side::Right);
-        'outer: for x in 0..self.width() {
-            for y in 0..self.height() {
-                if let Some(Tile::Battery {
-                    plus_side: plus,
-                    minus_side: minus,
-                }) = self.get(x, y).unwrap()
-                {
-                    (battery_x, battery_y, plus_side, minus_side) = (x, y, *plus, *minus);
-                    found_battery = true;
-                    break 'outer;
-                }
-            }
-        }
+    fn xy_from_world_pos(&self, pos: Vec2) -> (usize, usize) {
+        let mut normalized = (pos - Vec2::new(self.start_x(), self.start_y())) / self.tile_size();
+
+        normalized.x = round(normalized.x);
+        normalized.y = round(normalized.y);
 
-        if !found_battery {
-            return false;
+        if normalized.x < 0.0 {
+            normalized.x = 0.0;
+        }
+        if normalized.y > 0.0 {
+            normalized.y = 0.0;
+        }
+        if normalized.x >= self.width as f32 {
+            normalized.x = self.width as f32 - 1.0;
+        }
+        if normalized.y <= -(self.height as f32) {
+            normalized.y = self.height as f32 + 1.0;
         }
 
-        self.is_solved_helper(
-            battery_x as i32 + plus_side.x_offset(),
-            battery_y as i32 + plus_side.y_offset(),
-            battery_x as i32,
-            battery_y as i32,
-            Tile::Battery {
-                plus_side,
-                minus_side,
-            },
-            false,
-        )
+        (normalized.x as usize, -normalized.y as usize)
     }
-}
 
-#[derive(Component)]
-#[require(Node)]
-struct ButtonRoot;
+    fn snap_to_grid(&self, pos: Vec2) -> Vec2 {
+        let start = Vec2::new(self.start_x(), self.start_y());
 
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    // Load Sounds
-    commands.insert_resource(Sounds {
-        drop: asset_server.load("audio/drop.wav"),
+        let mut normalized = (pos - start) / self.tile_size();
+        normalized.x = round(normalized.x);
+        normalized.y = round(normalized.y);
+
+        if normalized.x < 0.0 {
+            normalized.x = 0.0;
+        }
+        if normalized.y > 0.0 {
+            normalized.y = 0.0;
+        }
+        if normalized.x >= self.width as f32 {
+            normalized.x = self.width as f32 - 1.0;
+        }
+        if normalized.y <= -(self.height as f32) {
+            normalized.y = self.height as f32 + 1.0;
+        }
+
+        normalized * self.tile_size() + start
+    }
+}
+
+/// Phones and tablets held upright get the buttons above the board and the HUD below it.
+fn is_portrait(screen: UVec2) -> bool {
+    screen.y > screen.x
+}
+
+fn button_root_node(screen: UVec2) -> Node {
+    match is_portrait(screen) {
+        // One or two rows across the top
+        true => Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.0),
+            right: Val::Px(10.0),
+            top: Val::Px(10.0),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Start,
+            flex_direction: FlexDirection::Row,
+            flex_wrap: FlexWrap::Wrap,
+            row_gap: Val::Px(10.0),
+            column_gap: Val::Px(10.0),
+            ..default()
+        },
+        false => Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(20.0),
+            top: Val::Px(10.0),
+            justify_content: JustifyContent::Start,
+            align_items: AlignItems::Start,
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(20.0),
+            ..default()
+        },
+    }
+}
+
+#[derive(Component)]
+#[require(Node)]
+struct ButtonRoot;
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    window: Single<&Window, With<PrimaryWindow>>,
+) {
+    commands.init_resource::<Hint>();
+    commands.init_resource::<GamepadCursor>();
+    commands.init_resource::<MoveHistory>();
+
+    // Load Sounds
+    commands.insert_resource(Sounds {
+        drop: asset_server.load("audio/drop.wav"),
         start_drag: asset_server.load("audio/start_drag.wav"),
         lamp_turns_on: asset_server.load("audio/lamp_on2.wav"),
         misdrop: asset_server.load("audio/misdrop.wav"),
+        spark: asset_server.load("audio/spark.wav"),
     });
 
     // Buttons
     let button_root = commands
-        .spawn((
-            ButtonRoot,
-            Node {
-                position_type: PositionType::Absolute,
-                left: Val::Px(20.0),
-                top: Val::Px(10.0),
-                justify_content: JustifyContent::Start,
-                align_items: AlignItems::Start,
-                flex_direction: FlexDirection::Column,
-                row_gap: Val::Px(20.0),
-                ..default()
-            },
-        ))
+        .spawn((ButtonRoot, button_root_node(window.size().as_uvec2())))
         .id();
 
-    let back_button = commands
-        .spawn((
-            Node {
-                padding: UiRect::all(Val::Px(6.0)),
-                justify_content: JustifyContent::Center,
-                align_items: AlignItems::Center,
-                ..default()
-            },
-            Button,
-            BackgroundColor(Color::srgb(0.2, 0.2, 0.2)),
-            BorderColor::all(Color::BLACK),
-            BorderRadius::all(Val::Px(10.0)),
-        ))
+    let back_button = spawn_game_button(&mut commands, "Zurueck zum Menu")
         .observe(
             |_trigger: On<Pointer<Click>>,
              mut next_app_state: ResMut<NextState<AppState>>,
@@ -755,58 +1083,169 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                 next_level_state.set(LevelState::Menu);
             },
         )
-        .with_children(|parent| {
-            parent.spawn((
-                Text::new("Zurueck zum Menu"),
-                TextFont {
-                    font_size: 23.0,
-                    ..default()
-                },
-                TextColor(Color::WHITE),
-            ));
-        })
         .id();
 
-    let restart_button = commands
-        .spawn((
-            Node {
-                padding: UiRect::all(Val::Px(6.0)),
-                justify_content: JustifyContent::Center,
-                align_items: AlignItems::Center,
-                ..default()
-            },
-            Button,
-            BackgroundColor(Color::srgb(0.2, 0.2, 0.2)),
-            BorderColor::all(Color::BLACK),
-            BorderRadius::all(Val::Px(10.0)),
-        ))
+    let restart_button = spawn_game_button(&mut commands, "Restart")
         .observe(|_trigger: On<Pointer<Click>>, mut commands: Commands| {
             info!("Restart button clicked!");
             commands.trigger(MakeNewPuzzleRequest);
         })
-        .with_children(|parent| {
-            parent.spawn((
-                Text::new("Restart"),
-                TextFont {
-                    font_size: 23.0,
-                    ..default()
-                },
-                TextColor(Color::WHITE),
-            ));
+        .id();
+
+    let hint_button = spawn_game_button(&mut commands, "Hint")
+        .observe(|_trigger: On<Pointer<Click>>, mut commands: Commands| {
+            info!("Hint button clicked!");
+            commands.trigger(HintRequest);
         })
         .id();
 
-    commands
-        .entity(button_root)
-        .add_children(&[back_button, restart_button]);
+    let undo_button = spawn_game_button(&mut commands, "Undo")
+        .observe(
+            |_trigger: On<Pointer<Click>>,
+             mut history: ResMut<MoveHistory>,
+             mut commands: Commands| {
+                info!("Undo button clicked!");
+                history.undo(&mut commands);
+            },
+        )
+        .id();
+
+    let redo_button = spawn_game_button(&mut commands, "Redo")
+        .observe(
+            |_trigger: On<Pointer<Click>>,
+             mut history: ResMut<MoveHistory>,
+             mut commands: Commands| {
+                info!("Redo button clicked!");
+                history.redo(&mut commands);
+            },
+        )
+        .id();
+
+    let convention_button = spawn_game_button(&mut commands, "Stromrichtung (C)")
+        .observe(
+            |_trigger: On<Pointer<Click>>, mut convention: ResMut<CurrentConvention>| {
+                info!("Convention button clicked!");
+                *convention = convention.toggled();
+            },
+        )
+        .id();
+
+    commands.entity(button_root).add_children(&[
+        back_button,
+        restart_button,
+        hint_button,
+        undo_button,
+        redo_button,
+        convention_button,
+    ]);
+}
+
+fn spawn_game_button<'a>(commands: &'a mut Commands, label: &str) -> EntityCommands<'a> {
+    let mut button = commands.spawn((
+        Node {
+            padding: UiRect::all(Val::Px(6.0)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        Button,
+        BackgroundColor(Color::srgb(0.2, 0.2, 0.2)),
+        BorderColor::all(Color::BLACK),
+        BorderRadius::all(Val::Px(10.0)),
+    ));
+    button.with_children(|parent| {
+        parent.spawn((
+            Text::new(label),
+            TextFont {
+                font_size: 23.0,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+        ));
+    });
+    button
+}
+
+/// Next move of the shortest solution, computed in the background when a hint is requested.
+#[derive(Resource, Default)]
+struct Hint {
+    pending: Option<Task<Option<Move>>>,
+    next_move: Option<Move>,
+}
+
+/// Asks for the next move of the shortest solution, from the hint button or the gamepad.
+#[derive(Event)]
+struct HintRequest;
+
+fn request_hint(_event: On<HintRequest>, grid: Res<Grid>, mut hint: ResMut<Hint>) {
+    let board = grid.clone();
+    hint.next_move = None;
+    hint.pending = Some(AsyncComputeTaskPool::get().spawn(async move {
+        let solution = solver::solve(&board)?;
+        info!(
+            "Puzzle can be solved in {} moves ({} boards explored)",
+            solution.moves.len(),
+            solution.explored
+        );
+        solution.moves.first().copied()
+    }));
+}
+
+fn poll_hint(mut hint: ResMut<Hint>) {
+    let Some(task) = &mut hint.pending else {
+        return;
+    };
+    let Some(next_move) = check_ready(task) else {
+        return;
+    };
+
+    if next_move.is_none() {
+        info!("No hint available, the puzzle is already solved or unsolvable");
+    }
+    hint.next_move = next_move;
+    hint.pending = None;
+}
+
+fn draw_hint(hint: Res<Hint>, layout: Res<BoardLayout>, mut gizmos: Gizmos) {
+    let Some(mv) = hint.next_move else {
+        return;
+    };
+
+    let tile_size = Vec2::splat(layout.tile_size());
+    let center = |(x, y): (usize, usize)| {
+        layout.world_pos_from_xy(x, y).unwrap() + Vec2::new(tile_size.x, -tile_size.y) / 2.0
+    };
+
+    match mv {
+        Move::Slide { from, to } => {
+            let (from, to) = (center(from), center(to));
+
+            gizmos.rect_2d(from, tile_size * 0.95, HINT_COLOR);
+            gizmos.rect_2d(to, tile_size * 0.95, HINT_COLOR);
+            gizmos.arrow_2d(from, to, HINT_COLOR);
+        }
+        Move::Toggle { cell } => {
+            gizmos.rect_2d(center(cell), tile_size * 0.95, HINT_COLOR);
+            gizmos.circle_2d(center(cell), tile_size.x * 0.3, HINT_COLOR);
+        }
+    }
 }
 
 fn get_path_to_start_sprite_for_tile(tile: &Tile) -> &'static str {
     match tile {
         Tile::P => "sprites/p.png",
         Tile::N => "sprites/n.png",
+        Tile::Cross => "sprites/cross.png",
+        Tile::Crossover => "sprites/crossover.png",
+
+        Tile::TJunction { missing } => match missing {
+            Side::Left => "sprites/t_junction_missing_left.png",
+            Side::Right => "sprites/t_junction_missing_right.png",
+            Side::Top => "sprites/t_junction_missing_top.png",
+            Side::Bottom => "sprites/t_junction_missing_bottom.png",
+        },
 
-        Tile::Lamp { entry, exit } => match (entry, exit) {
+        Tile::Lamp { entry, exit, .. } => match (entry, exit) {
             (Side::Right, Side::Bottom) => "sprites/lamp_off_right_to_bottom.png",
             (Side::Right, Side::Left) => "sprites/lamp_off_right_to_left.png",
             (Side::Right, Side::Top) => "sprites/lamp_off_right_to_top.png",
@@ -826,6 +1265,26 @@ fn get_path_to_start_sprite_for_tile(tile: &Tile) -> &'static str {
             _ => panic!(),
         },
 
+        Tile::Led { anode, cathode, .. } => match (anode, cathode) {
+            (Side::Right, Side::Bottom) => "sprites/led_off_right_to_bottom.png",
+            (Side::Right, Side::Left) => "sprites/led_off_right_to_left.png",
+            (Side::Right, Side::Top) => "sprites/led_off_right_to_top.png",
+
+            (Side::Bottom, Side::Right) => "sprites/led_off_bottom_to_right.png",
+            (Side::Bottom, Side::Left) => "sprites/led_off_bottom_to_left.png",
+            (Side::Bottom, Side::Top) => "sprites/led_off_bottom_to_top.png",
+
+            (Side::Left, Side::Right) => "sprites/led_off_left_to_right.png",
+            (Side::Left, Side::Bottom) => "sprites/led_off_left_to_bottom.png",
+            (Side::Left, Side::Top) => "sprites/led_off_left_to_top.png",
+
+            (Side::Top, Side::Right) => "sprites/led_off_top_to_right.png",
+            (Side::Top, Side::Bottom) => "sprites/led_off_top_to_bottom.png",
+            (Side::Top, Side::Left) => "sprites/led_off_top_to_left.png",
+
+            _ => panic!(),
+        },
+
         Tile::Battery {
             plus_side,
             minus_side,
@@ -868,11 +1327,99 @@ fn get_path_to_start_sprite_for_tile(tile: &Tile) -> &'static str {
 
             _ => panic!(),
         },
+
+        Tile::Resistor { entry, exit, .. } => match (entry, exit) {
+            (Side::Right, Side::Bottom) => "sprites/resistor_right_to_bottom.png",
+            (Side::Right, Side::Left) => "sprites/resistor_right_to_left.png",
+            (Side::Right, Side::Top) => "sprites/resistor_right_to_top.png",
+
+            (Side::Bottom, Side::Right) => "sprites/resistor_bottom_to_right.png",
+            (Side::Bottom, Side::Left) => "sprites/resistor_bottom_to_left.png",
+            (Side::Bottom, Side::Top) => "sprites/resistor_bottom_to_top.png",
+
+            (Side::Left, Side::Right) => "sprites/resistor_left_to_right.png",
+            (Side::Left, Side::Bottom) => "sprites/resistor_left_to_bottom.png",
+            (Side::Left, Side::Top) => "sprites/resistor_left_to_top.png",
+
+            (Side::Top, Side::Right) => "sprites/resistor_top_to_right.png",
+            (Side::Top, Side::Bottom) => "sprites/resistor_top_to_bottom.png",
+            (Side::Top, Side::Left) => "sprites/resistor_top_to_left.png",
+
+            _ => panic!(),
+        },
+
+        Tile::Capacitor { entry, exit } => match (entry, exit) {
+            (Side::Right, Side::Bottom) => "sprites/capacitor_right_to_bottom.png",
+            (Side::Right, Side::Left) => "sprites/capacitor_right_to_left.png",
+            (Side::Right, Side::Top) => "sprites/capacitor_right_to_top.png",
+
+            (Side::Bottom, Side::Right) => "sprites/capacitor_bottom_to_right.png",
+            (Side::Bottom, Side::Left) => "sprites/capacitor_bottom_to_left.png",
+            (Side::Bottom, Side::Top) => "sprites/capacitor_bottom_to_top.png",
+
+            (Side::Left, Side::Right) => "sprites/capacitor_left_to_right.png",
+            (Side::Left, Side::Bottom) => "sprites/capacitor_left_to_bottom.png",
+            (Side::Left, Side::Top) => "sprites/capacitor_left_to_top.png",
+
+            (Side::Top, Side::Right) => "sprites/capacitor_top_to_right.png",
+            (Side::Top, Side::Bottom) => "sprites/capacitor_top_to_bottom.png",
+            (Side::Top, Side::Left) => "sprites/capacitor_top_to_left.png",
+
+            _ => panic!(),
+        },
+
+        Tile::Switch {
+            entry,
+            exit,
+            closed: false,
+        } => match (entry, exit) {
+            (Side::Right, Side::Bottom) => "sprites/switch_open_right_to_bottom.png",
+            (Side::Right, Side::Left) => "sprites/switch_open_right_to_left.png",
+            (Side::Right, Side::Top) => "sprites/switch_open_right_to_top.png",
+
+            (Side::Bottom, Side::Right) => "sprites/switch_open_bottom_to_right.png",
+            (Side::Bottom, Side::Left) => "sprites/switch_open_bottom_to_left.png",
+            (Side::Bottom, Side::Top) => "sprites/switch_open_bottom_to_top.png",
+
+            (Side::Left, Side::Right) => "sprites/switch_open_left_to_right.png",
+            (Side::Left, Side::Bottom) => "sprites/switch_open_left_to_bottom.png",
+            (Side::Left, Side::Top) => "sprites/switch_open_left_to_top.png",
+
+            (Side::Top, Side::Right) => "sprites/switch_open_top_to_right.png",
+            (Side::Top, Side::Bottom) => "sprites/switch_open_top_to_bottom.png",
+            (Side::Top, Side::Left) => "sprites/switch_open_top_to_left.png",
+
+            _ => panic!(),
+        },
+
+        Tile::Switch {
+            entry,
+            exit,
+            closed: true,
+        } => match (entry, exit) {
+            (Side::Right, Side::Bottom) => "sprites/switch_closed_right_to_bottom.png",
+            (Side::Right, Side::Left) => "sprites/switch_closed_right_to_left.png",
+            (Side::Right, Side::Top) => "sprites/switch_closed_right_to_top.png",
+
+            (Side::Bottom, Side::Right) => "sprites/switch_closed_bottom_to_right.png",
+            (Side::Bottom, Side::Left) => "sprites/switch_closed_bottom_to_left.png",
+            (Side::Bottom, Side::Top) => "sprites/switch_closed_bottom_to_top.png",
+
+            (Side::Left, Side::Right) => "sprites/switch_closed_left_to_right.png",
+            (Side::Left, Side::Bottom) => "sprites/switch_closed_left_to_bottom.png",
+            (Side::Left, Side::Top) => "sprites/switch_closed_left_to_top.png",
+
+            (Side::Top, Side::Right) => "sprites/switch_closed_top_to_right.png",
+            (Side::Top, Side::Bottom) => "sprites/switch_closed_top_to_bottom.png",
+            (Side::Top, Side::Left) => "sprites/switch_closed_top_to_left.png",
+
+            _ => panic!(),
+        },
     }
 }
 
 fn get_path_to_lamp_on_sprite_for_tile(tile: &Tile) -> &'static str {
-    let Tile::Lamp { entry, exit } = tile else {
+    let Tile::Lamp { entry, exit, .. } = tile else {
         unreachable!()
     };
     match (entry, exit) {
@@ -896,6 +1443,31 @@ fn get_path_to_lamp_on_sprite_for_tile(tile: &Tile) -> &'static str {
     }
 }
 
+fn get_path_to_led_on_sprite_for_tile(tile: &Tile) -> &'static str {
+    let Tile::Led { anode, cathode, .. } = tile else {
+        unreachable!()
+    };
+    match (anode, cathode) {
+        (Side::Right, Side::Bottom) => "sprites/led_on_right_to_bottom.png",
+        (Side::Right, Side::Left) => "sprites/led_on_right_to_left.png",
+        (Side::Right, Side::Top) => "sprites/led_on_right_to_top.png",
+
+        (Side::Bottom, Side::Right) => "sprites/led_on_bottom_to_right.png",
+        (Side::Bottom, Side::Left) => "sprites/led_on_bottom_to_left.png",
+        (Side::Bottom, Side::Top) => "sprites/led_on_bottom_to_top.png",
+
+        (Side::Left, Side::Right) => "sprites/led_on_left_to_right.png",
+        (Side::Left, Side::Bottom) => "sprites/led_on_left_to_bottom.png",
+        (Side::Left, Side::Top) => "sprites/led_on_left_to_top.png",
+
+        (Side::Top, Side::Right) => "sprites/led_on_top_to_right.png",
+        (Side::Top, Side::Bottom) => "sprites/led_on_top_to_bottom.png",
+        (Side::Top, Side::Left) => "sprites/led_on_top_to_left.png",
+
+        _ => panic!(),
+    }
+}
+
 #[derive(Event)]
 struct MakeNewPuzzleRequest;
 
@@ -904,7 +1476,13 @@ fn cleanup_puzzle(
     tiles: Query<Entity, With<TileComponent>>,
     grid_lines: Query<Entity, With<GridLine>>,
     buttons: Query<Entity, With<Button>>,
+    hud: Query<Entity, With<HudText>>,
+    flashes: Query<Entity, With<ShortCircuitFlash>>,
+    mut drag: ResMut<TileDragSystemState>,
 ) {
+    // Let go of the dragged tile before it is despawned
+    drag.current = None;
+
     // Despawn tile entities (and any children)
     for entity in tiles.iter() {
         commands.entity(entity).despawn();
@@ -920,25 +1498,56 @@ fn cleanup_puzzle(
         commands.entity(entity).despawn();
     }
 
+    // Despawn HUD
+    for entity in hud.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    // Despawn short circuit warning
+    for entity in flashes.iter() {
+        commands.entity(entity).despawn();
+    }
+
     // Remove runtime resources if present
     commands.remove_resource::<Grid>();
+    commands.remove_resource::<BoardLayout>();
     commands.remove_resource::<Sounds>();
+    commands.remove_resource::<Hint>();
+    commands.remove_resource::<GamepadCursor>();
+    commands.remove_resource::<MoveHistory>();
+    commands.remove_resource::<PuzzleStats>();
+    commands.remove_resource::<CurrentFlow>();
+    commands.remove_resource::<CircuitState>();
+    commands.remove_resource::<LitLamps>();
 }
 
+#[allow(clippy::too_many_arguments)]
 fn new_puzzle(
     _event: On<MakeNewPuzzleRequest>,
     mut commands: Commands,
+    window: Single<&Window, With<PrimaryWindow>>,
     tiles: Query<(Entity, &TileComponent)>,
     grid_lines: Query<Entity, With<GridLine>>,
     camera: Single<(Entity, &Tonemapping, Option<&mut Bloom>), With<Camera>>,
     asset_server: Res<AssetServer>,
     level_state: Res<State<LevelState>>,
+    level_assets: Res<Assets<Level>>,
+    mut drag: ResMut<TileDragSystemState>,
 ) {
+    let Some(level) = level_state.level(&level_assets) else {
+        warn!("No level to play in {:?}", level_state.get());
+        return;
+    };
+    info!("Starting level {} ({:?})", level.name, level.difficulty);
+
     // Reset Tonemapping and Bloom
     let (camera_entity, _, _) = camera.into_inner();
     commands.entity(camera_entity).remove::<Bloom>();
     commands.entity(camera_entity).insert(Tonemapping::None);
 
+    // Let go of the dragged tile before it is despawned
+    drag.current = None;
+
     // Delete previous tiles
     for (entity, _) in tiles.iter() {
         commands.entity(entity).despawn();
@@ -950,47 +1559,17 @@ fn new_puzzle(
     }
 
     // Create New;
-    let grid = generate_puzzle(level_state);
+    let grid = generate_puzzle(level);
+    let layout = BoardLayout::new(&grid, window.size().as_uvec2());
     commands.insert_resource(grid.clone());
+    commands.insert_resource(layout);
+    commands.insert_resource(Hint::default());
+    commands.insert_resource(MoveHistory::default());
+    commands.insert_resource(LitLamps::default());
 
     // UI
-    let tile_size = Vec2::new(grid.tile_size(), grid.tile_size());
-
-    // Lines
-    let thickness = 1.0;
-    let grid_pixel_w = grid.width() as f32 * tile_size.x;
-    let grid_pixel_h = grid.height() as f32 * tile_size.y;
-    let start = Vec2::new(grid.start_x(), grid.start_y());
-
-    // Vertical lines
-    for i in 0..=grid.width() {
-        let x = start.x + i as f32 * tile_size.x;
-        commands.spawn((
-            GridLine,
-            Anchor::TOP_LEFT,
-            Sprite {
-                color: Color::linear_rgb(0.75, 0.75, 0.75),
-                custom_size: Some(Vec2::new(thickness, grid_pixel_h)),
-                ..default()
-            },
-            Transform::from_translation(Vec3::new(x - thickness / 2.0, start.y, -1.0)),
-        ));
-    }
-
-    // Horizontal lines
-    for j in 0..=grid.height() {
-        let y = start.y - j as f32 * tile_size.y;
-        commands.spawn((
-            GridLine,
-            Anchor::TOP_LEFT,
-            Sprite {
-                color: Color::WHITE,
-                custom_size: Some(Vec2::new(grid_pixel_w, thickness)),
-                ..default()
-            },
-            Transform::from_translation(Vec3::new(start.x, y - thickness / 2.0, -1.0)),
-        ));
-    }
+    let tile_size = Vec2::new(layout.tile_size(), layout.tile_size());
+    spawn_grid_lines(&mut commands, &layout);
 
     // Tiles
     for x in 0..grid.width() {
@@ -1003,161 +1582,696 @@ fn new_puzzle(
             let sprite_path = get_path_to_start_sprite_for_tile(tile);
             let sprite = asset_server.load(sprite_path);
 
-            let pos = grid.world_pos_from_xy(x, y).unwrap();
+            let pos = layout.world_pos_from_xy(x, y).unwrap();
 
-            commands.spawn((
+            let color = match tile {
+                Tile::Led { color, .. } => color.dark(),
+                _ => Color::WHITE,
+            };
+
+            let mut tile_entity = commands.spawn((
                 TileComponent { x, y },
                 Anchor::TOP_LEFT,
                 Sprite {
                     image: sprite,
+                    color,
                     custom_size: Some(tile_size),
                     ..default()
                 },
                 Transform::from_translation(Vec3::new(pos.x, pos.y, 0.0)),
             ));
+
+            spawn_carriers(&mut tile_entity, tile, tile_size.y);
+
+            if let Tile::Lamp { id: Some(id), .. } = tile {
+                let label = TileLabel {
+                    offset: Vec2::new(0.15, 0.15),
+                    font_size: 0.2,
+                };
+                tile_entity.with_child((
+                    Text2d::new(id.to_string()),
+                    TextFont {
+                        font_size: label.font_size * tile_size.y,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                    label.transform(tile_size.y),
+                    label,
+                ));
+            }
+
+            if let Tile::Capacitor { .. } = tile {
+                tile_entity.insert(CapacitorCharge::default());
+            }
+
+            if let Tile::Resistor { ohms, .. } = tile {
+                let label = TileLabel {
+                    offset: Vec2::new(0.5, 0.5),
+                    font_size: 0.1,
+                };
+                tile_entity.with_child((
+                    Text2d::new(format!("{}\nOhm", ohms)),
+                    TextFont {
+                        font_size: label.font_size * tile_size.y,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                    TextLayout::new_with_justify(Justify::Center),
+                    label.transform(tile_size.y),
+                    label,
+                ));
+            }
         }
     }
+
+    commands.trigger(CircuitChanged);
 }
-fn generate_puzzle(level_state: Res<State<LevelState>>) -> Grid {
-    let easy = vec![
-        Some(Tile::Cable {
-            entry: Side::Right,
-            exit: Side::Bottom,
-        }),
-        Some(Tile::Battery {
-            plus_side: Side::Left,
-            minus_side: Side::Right,
-        }),
-        Some(Tile::N),
-        Some(Tile::Cable {
-            entry: Side::Top,
-            exit: Side::Right,
-        }),
-        Some(Tile::Lamp {
-            entry: Side::Left,
-            exit: Side::Bottom,
-        }),
-        Some(Tile::P),
-        None,
-        Some(Tile::Cable {
-            entry: Side::Top,
-            exit: Side::Right,
-        }),
-        Some(Tile::Cable {
-            entry: Side::Left,
-            exit: Side::Top,
-        }),
-    ];
-
-    let medium = vec![
-        None,
-        Some(Tile::N),
-        Some(Tile::P),
-        Some(Tile::Battery {
-            plus_side: Side::Left,
-            minus_side: Side::Top,
-        }),
-        Some(Tile::Cable {
-            entry: Side::Right,
-            exit: Side::Left,
-        }),
-        Some(Tile::Lamp {
-            entry: Side::Right,
-            exit: Side::Top,
-        }),
-        Some(Tile::Cable {
-            entry: Side::Bottom,
-            exit: Side::Right,
-        }),
-        Some(Tile::Cable {
-            entry: Side::Left,
-            exit: Side::Top,
-        }),
-        Some(Tile::Cable {
-            entry: Side::Bottom,
-            exit: Side::Right,
-        }),
-    ];
-
-    let hard = vec![
-        Some(Tile::N),
-        Some(Tile::P),
-        None,
-        Some(Tile::Cable {
-            entry: Side::Top,
-            exit: Side::Bottom,
-        }),
-        Some(Tile::Cable {
-            entry: Side::Bottom,
-            exit: Side::Right,
-        }),
-        Some(Tile::Cable {
-            entry: Side::Left,
-            exit: Side::Top,
-        }),
-        Some(Tile::Cable {
-            entry: Side::Bottom,
-            exit: Side::Left,
-        }),
-        Some(Tile::Lamp {
-            entry: Side::Right,
-            exit: Side::Left,
-        }),
-        Some(Tile::Battery {
-            plus_side: Side::Bottom,
-            minus_side: Side::Right,
-        }),
-    ];
-
-    let extreme = vec![
-        Some(Tile::N),
-        Some(Tile::P),
-        None,
-        Some(Tile::Cable {
-            entry: Side::Top,
-            exit: Side::Bottom,
-        }),
-        Some(Tile::Battery {
-            plus_side: Side::Top,
-            minus_side: Side::Right,
-        }),
-        Some(Tile::Lamp {
-            entry: Side::Bottom,
-            exit: Side::Left,
-        }),
-        Some(Tile::Cable {
-            entry: Side::Top,
-            exit: Side::Right,
-        }),
-        Some(Tile::Cable {
-            entry: Side::Left,
-            exit: Side::Right,
-        }),
-        Some(Tile::Cable {
-            entry: Side::Left,
-            exit: Side::Top,
-        }),
-    ];
 
-    let mut level;
+/// Fits the board to the window again after it was resized or rotated. Tiles move to their new
+/// cells, and everything drawn on them is sized from the new tile size.
+fn relayout_board(
+    mut resized: MessageReader<WindowResized>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    mut commands: Commands,
+    mut layout: ResMut<BoardLayout>,
+    mut tiles: Query<(&TileComponent, &mut Transform, &mut Sprite, Has<Sliding>)>,
+    mut labels: Query<(&TileLabel, &mut Transform, &mut TextFont), Without<TileComponent>>,
+    grid_lines: Query<Entity, With<GridLine>>,
+) {
+    if resized.read().last().is_none() {
+        return;
+    }
+    // A minimized window has no size
+    let screen = window.size().as_uvec2();
+    if screen == layout.screen || screen.min_element() == 0 {
+        return;
+    }
+
+    layout.screen = screen;
+    let tile_size = layout.tile_size();
+
+    for (tile, mut transform, mut sprite, sliding) in tiles.iter_mut() {
+        // Sliding tiles head for their new cell by themselves
+        if !sliding {
+            let pos = layout.world_pos_from_xy(tile.x, tile.y).unwrap();
+            transform.translation = pos.extend(transform.translation.z);
+        }
+        sprite.custom_size = Some(Vec2::splat(tile_size));
+    }
+
+    for (label, mut transform, mut font) in labels.iter_mut() {
+        *transform = label.transform(tile_size);
+        font.font_size = label.font_size * tile_size;
+    }
+
+    for entity in grid_lines.iter() {
+        commands.entity(entity).despawn();
+    }
+    spawn_grid_lines(&mut commands, &layout);
+
+    // Depletion layers and the current loop are drawn in world coordinates
+    commands.trigger(CircuitChanged);
+}
+
+/// Moves the buttons and the HUD when the window turns from landscape to portrait or back.
+fn relayout_ui(
+    mut resized: MessageReader<WindowResized>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    mut button_roots: Query<&mut Node, With<ButtonRoot>>,
+    mut huds: Query<&mut Node, (With<HudText>, Without<ButtonRoot>)>,
+) {
+    if resized.read().last().is_none() {
+        return;
+    }
+
+    let screen = window.size().as_uvec2();
+    for mut node in button_roots.iter_mut() {
+        *node = button_root_node(screen);
+    }
+    for mut node in huds.iter_mut() {
+        *node = hud_node(screen);
+    }
+}
+
+/// Draws the lines between the cells of the board.
+fn spawn_grid_lines(commands: &mut Commands, layout: &BoardLayout) {
+    let tile_size = Vec2::new(layout.tile_size(), layout.tile_size());
+    let thickness = 1.0;
+    let grid_pixel_w = layout.width as f32 * tile_size.x;
+    let grid_pixel_h = layout.height as f32 * tile_size.y;
+    let start = Vec2::new(layout.start_x(), layout.start_y());
+
+    // Vertical lines
+    for i in 0..=layout.width {
+        let x = start.x + i as f32 * tile_size.x;
+        commands.spawn((
+            GridLine,
+            Anchor::TOP_LEFT,
+            Sprite {
+                color: Color::linear_rgb(0.75, 0.75, 0.75),
+                custom_size: Some(Vec2::new(thickness, grid_pixel_h)),
+                ..default()
+            },
+            Transform::from_translation(Vec3::new(x - thickness / 2.0, start.y, -1.0)),
+        ));
+    }
+
+    // Horizontal lines
+    for j in 0..=layout.height {
+        let y = start.y - j as f32 * tile_size.y;
+        commands.spawn((
+            GridLine,
+            Anchor::TOP_LEFT,
+            Sprite {
+                color: Color::WHITE,
+                custom_size: Some(Vec2::new(grid_pixel_w, thickness)),
+                ..default()
+            },
+            Transform::from_translation(Vec3::new(start.x, y - thickness / 2.0, -1.0)),
+        ));
+    }
+}
+
+const SCRAMBLE_MOVES: usize = 40;
+
+fn generate_puzzle(level: &Level) -> Grid {
+    let mut grid = Grid::new(level);
+    grid.scramble(&mut rng());
+    grid
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum MoveKind {
+    Play,
+    Undo,
+    Redo,
+}
+
+#[derive(Event)]
+struct MoveTileRequest {
+    mv: Move,
+    kind: MoveKind,
+}
+
+/// A move was made on the board, after `Grid` was updated.
+#[derive(Event)]
+struct TileMoved {
+    mv: Move,
+    kind: MoveKind,
+}
+
+/// The board was rebuilt or changed, so everything drawn from the circuit has to be updated.
+#[derive(Event)]
+struct CircuitChanged;
+
+/// Drawn over the edge between a P and an N tile, as a child of the P tile.
+#[derive(Component)]
+struct DepletionLayer;
+
+/// Width of an unbiased depletion layer, as a share of the tile size.
+const DEPLETION_LAYER_WIDTH: f32 = 0.2;
+const DEPLETION_LAYER_COLOR: Color = Color::srgba(1.0, 1.0, 1.0, 0.25);
+
+/// Voltage of a capacitor tile, which it keeps while it slides to another cell.
+#[derive(Component, Default)]
+struct CapacitorCharge(f64);
+
+/// Currents on the board at the latest time step of `simulate_circuit`.
+#[derive(Resource)]
+struct CircuitState(Analysis);
+
+/// Longest time step of the circuit simulation, a longer frame is simulated as this long.
+const MAX_CIRCUIT_STEP: f32 = 0.05;
+
+/// Tile is animated towards its grid cell by `slide_tiles`.
+#[derive(Component)]
+struct Sliding;
+
+const SLIDE_DECAY_RATE: f32 = 20.0;
+
+#[derive(Resource, Default)]
+struct MoveHistory {
+    undo: Vec<Move>,
+    redo: Vec<Move>,
+}
+impl MoveHistory {
+    fn record(&mut self, mv: Move, kind: MoveKind) {
+        match kind {
+            MoveKind::Play => {
+                self.undo.push(mv);
+                self.redo.clear();
+            }
+            MoveKind::Undo => self.redo.push(mv.reversed()),
+            MoveKind::Redo => self.undo.push(mv),
+        }
+    }
+
+    fn undo(&mut self, commands: &mut Commands) {
+        if let Some(mv) = self.undo.pop() {
+            commands.trigger(MoveTileRequest {
+                mv: mv.reversed(),
+                kind: MoveKind::Undo,
+            });
+        }
+    }
 
-    match level_state.get() {
-        LevelState::Easy => {
-            level = easy.clone();
+    fn redo(&mut self, commands: &mut Commands) {
+        if let Some(mv) = self.redo.pop() {
+            commands.trigger(MoveTileRequest {
+                mv,
+                kind: MoveKind::Redo,
+            });
         }
-        LevelState::Medium => {
-            level = medium.clone();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn move_tile(
+    request: On<MoveTileRequest>,
+    mut commands: Commands,
+    mut tiles: Query<(Entity, &mut TileComponent, &mut Sprite)>,
+    mut grid: ResMut<Grid>,
+    mut history: ResMut<MoveHistory>,
+    mut hint: ResMut<Hint>,
+    asset_server: Res<AssetServer>,
+    sounds: Res<Sounds>,
+) {
+    let mv = request.mv;
+    if !grid.is_legal_move(mv) {
+        warn!("Ignoring illegal move {:?}", mv);
+        return;
+    }
+
+    // Update Grid
+    grid.apply(mv);
+    history.record(mv, request.kind);
+    *hint = Hint::default();
+
+    match mv {
+        Move::Slide { from, to } => {
+            if let Some((entity, mut tile, _)) = tiles
+                .iter_mut()
+                .find(|(_, tile, _)| (tile.x, tile.y) == from)
+            {
+                tile.x = to.0;
+                tile.y = to.1;
+                commands.entity(entity).insert(Sliding);
+            }
         }
-        LevelState::Hard => {
-            level = hard.clone();
+        Move::Toggle { cell } => {
+            let switch = grid.get(cell.0, cell.1).unwrap().unwrap();
+            if let Some((_, _, mut sprite)) = tiles
+                .iter_mut()
+                .find(|(_, tile, _)| (tile.x, tile.y) == cell)
+            {
+                sprite.image = asset_server.load(get_path_to_start_sprite_for_tile(&switch));
+            }
         }
-        _ => {
-            level = extreme.clone();
+    }
+
+    // Audio
+    commands.spawn((AudioPlayer::new(sounds.drop.clone()), {
+        let mut settings = PlaybackSettings::DESPAWN;
+        settings.volume = Volume::Linear(0.15);
+        settings
+    }));
+
+    commands.trigger(TileMoved {
+        mv,
+        kind: request.kind,
+    });
+    commands.trigger(CircuitChanged);
+}
+
+fn slide_tiles(
+    mut commands: Commands,
+    mut tiles: Query<(Entity, &TileComponent, &mut Transform), With<Sliding>>,
+    layout: Res<BoardLayout>,
+    time: Res<Time>,
+) {
+    for (entity, tile, mut transform) in tiles.iter_mut() {
+        let target = layout.world_pos_from_xy(tile.x, tile.y).unwrap();
+
+        let mut pos = transform.translation.xy();
+        pos.smooth_nudge(&target, SLIDE_DECAY_RATE, time.delta_secs());
+
+        if pos.distance(target) < 0.5 {
+            transform.translation = target.extend(0.0);
+            commands.entity(entity).remove::<Sliding>();
+        } else {
+            transform.translation = pos.extend(10.0);
         }
     }
+}
+
+/// Advances the circuit by one frame: finds the currents for the present capacitor charges, then
+/// charges or discharges the capacitors with them.
+fn simulate_circuit(
+    mut commands: Commands,
+    mut capacitors: Query<(&TileComponent, &mut CapacitorCharge)>,
+    grid: Res<Grid>,
+    time: Res<Time>,
+) {
+    let charges: Charges = capacitors
+        .iter()
+        .map(|(tile, charge)| ((tile.x, tile.y), charge.0))
+        .collect();
+    let analysis = circuit::simulate(&grid, &charges);
+
+    let seconds = f64::from(time.delta_secs().min(MAX_CIRCUIT_STEP));
+    for (tile, mut charge) in capacitors.iter_mut() {
+        charge.0 = analysis.charge_after((tile.x, tile.y), seconds);
+    }
+
+    commands.insert_resource(CircuitState(analysis));
+}
 
-    level.shuffle(&mut rng());
-    return Grid(grid::Grid::from_vec(level, 3));
+/// What `light_lamps` showed in the previous frame, cleared with the board.
+#[derive(Resource, Default)]
+struct LitLamps {
+    /// Brightness of the brightest lamp.
+    brightest: f32,
+    /// Whether each lamp and LED tile was lit, so its sprite is only swapped when that changes.
+    lit: HashMap<Entity, bool>,
+}
+
+/// Lets the lamps and LEDs glow with the current of this time step. Whether the puzzle is solved
+/// is decided by `check_solved` instead.
+fn light_lamps(
+    mut shown: ResMut<LitLamps>,
+    camera: Single<Entity, With<Camera>>,
+    mut tiles: Query<(Entity, &TileComponent, &mut Sprite)>,
+    circuit: Res<CircuitState>,
+    grid: Res<Grid>,
+    asset_server: Res<AssetServer>,
+    mut commands: Commands,
+) {
+    let camera_entity = camera.into_inner();
+
+    let analysis = &circuit.0;
+    let mut brightest: f32 = 0.0;
+
+    for (entity, tile, mut sprite) in tiles.iter_mut() {
+        let Some(lamp @ (Tile::Led { .. } | Tile::Lamp { .. })) = grid.get(tile.x, tile.y).unwrap()
+        else {
+            continue;
+        };
+        let brightness = analysis.brightness((tile.x, tile.y));
+        brightest = brightest.max(brightness);
+
+        let lit = analysis.is_lit((tile.x, tile.y));
+        if shown.lit.insert(entity, lit) != Some(lit) {
+            let path = match (lamp, lit) {
+                (Tile::Led { .. }, true) => get_path_to_led_on_sprite_for_tile(lamp),
+                (_, true) => get_path_to_lamp_on_sprite_for_tile(lamp),
+                (_, false) => get_path_to_start_sprite_for_tile(lamp),
+            };
+            sprite.image = asset_server.load(path);
+        }
+
+        sprite.color = match (lamp, lit) {
+            (Tile::Led { color, .. }, true) => color.glow(brightness),
+            (Tile::Led { color, .. }, false) => color.dark(),
+            (_, true) => lamp_color(brightness),
+            (_, false) => Color::WHITE,
+        };
+    }
+
+    // Only touch the camera when the brightness changed visibly
+    let lit = brightest > 0.0;
+    let changed = lit != (shown.brightest > 0.0) || (brightest - shown.brightest).abs() >= 0.01;
+    if changed && lit {
+        // Bloom
+        commands.entity(camera_entity).remove::<Bloom>();
+        commands.entity(camera_entity).insert((
+            Bloom {
+                composite_mode: BloomCompositeMode::Additive,
+                intensity: 0.1 * brightest.clamp(0.25, MAX_LAMP_BRIGHTNESS),
+                low_frequency_boost_curvature: 0.9,
+                low_frequency_boost: 0.75,
+                scale: Vec2::splat(2.06),
+                prefilter: BloomPrefilter {
+                    threshold: 0.6,
+                    threshold_softness: 0.0,
+                },
+                ..default()
+            },
+            Tonemapping::TonyMcMapface,
+        ));
+    } else if changed {
+        // Bloom
+        commands.entity(camera_entity).remove::<Bloom>();
+        commands.entity(camera_entity).insert(Tonemapping::None);
+    }
+    if changed {
+        shown.brightest = brightest;
+    }
+}
+
+/// Finishes the puzzle as soon as the board becomes solved. Like the scrambler and the solver this
+/// goes by `Grid::is_solved`, so lamps that only glow while a capacitor charges or discharges do
+/// not count, unless the goal is `Goal::Flash`.
+fn check_solved(
+    _event: On<CircuitChanged>,
+    mut was_solved: Local<bool>,
+    grid: Res<Grid>,
+    mut commands: Commands,
+    sounds: Res<Sounds>,
+) {
+    let solved = grid.is_solved();
+    if solved && !*was_solved {
+        // Audio
+        commands.spawn((
+            AudioPlayer::new(sounds.lamp_turns_on.clone()),
+            PlaybackSettings::DESPAWN,
+        ));
+
+        commands.trigger(PuzzleSolved);
+    }
+    *was_solved = solved;
+}
+
+fn check_short_circuit(_event: On<TileMoved>, grid: Res<Grid>, mut commands: Commands) {
+    if circuit::analyze(&grid).is_short_circuit() {
+        commands.trigger(ShortCircuit);
+    }
+}
+
+/// Draws the depletion layer of every PN junction, wide under reverse bias and narrow under
+/// forward bias, and keeps the carriers of both tiles out of it.
+fn show_depletion_layers(
+    _event: On<CircuitChanged>,
+    mut commands: Commands,
+    layers: Query<Entity, With<DepletionLayer>>,
+    tiles: Query<(Entity, &TileComponent)>,
+    mut carrier_zones: Query<(&TileComponent, &mut DepletionZones)>,
+    grid: Res<Grid>,
+    layout: Res<BoardLayout>,
+) {
+    for entity in layers.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let analysis = circuit::analyze(&grid);
+    let tile_size = layout.tile_size();
+    let mut zones: HashMap<(usize, usize), DepletionZones> = HashMap::new();
+
+    for (entity, tile) in tiles.iter() {
+        if grid.get(tile.x, tile.y) != Some(&Some(Tile::P)) {
+            continue;
+        }
+
+        for side in [Side::Left, Side::Right, Side::Top, Side::Bottom] {
+            let Some(width) = analysis.depletion_width((tile.x, tile.y), side) else {
+                continue;
+            };
+            let width = width as f32 * DEPLETION_LAYER_WIDTH;
+
+            // Half of the layer lies in each tile
+            *zones.entry((tile.x, tile.y)).or_default().side_mut(side) = width / 2.0;
+            let n_cell = (
+                tile.x.wrapping_add_signed(side.x_offset() as isize),
+                tile.y.wrapping_add_signed(side.y_offset() as isize),
+            );
+            *zones.entry(n_cell).or_default().side_mut(side.opposite()) = width / 2.0;
+
+            let width = width * tile_size;
+            let length = tile_size * 0.9;
+
+            // Relative to the top left corner of the P tile
+            let (size, center) = match side {
+                Side::Left => (Vec2::new(width, length), Vec2::new(0.0, -tile_size / 2.0)),
+                Side::Right => (
+                    Vec2::new(width, length),
+                    Vec2::new(tile_size, -tile_size / 2.0),
+                ),
+                Side::Top => (Vec2::new(length, width), Vec2::new(tile_size / 2.0, 0.0)),
+                Side::Bottom => (
+                    Vec2::new(length, width),
+                    Vec2::new(tile_size / 2.0, -tile_size),
+                ),
+            };
+
+            commands.entity(entity).with_child((
+                DepletionLayer,
+                Sprite {
+                    color: DEPLETION_LAYER_COLOR,
+                    custom_size: Some(size),
+                    ..default()
+                },
+                Transform::from_translation(center.extend(1.0)),
+            ));
+        }
+    }
+
+    for (tile, mut tile_zones) in carrier_zones.iter_mut() {
+        *tile_zones = zones.remove(&(tile.x, tile.y)).unwrap_or_default();
+    }
+}
+
+/// Red overlay with an explanation, shown when a move short-circuits a battery.
+#[derive(Component)]
+struct ShortCircuitFlash {
+    timer: Timer,
+}
+
+const SHORT_CIRCUIT_SECONDS: f32 = 4.0;
+const SHORT_CIRCUIT_COLOR: Color = Color::srgba(1.0, 0.0, 0.0, 0.5);
+
+fn show_short_circuit(
+    _event: On<ShortCircuit>,
+    mut commands: Commands,
+    flashes: Query<Entity, With<ShortCircuitFlash>>,
+    sounds: Res<Sounds>,
+) {
+    for entity in flashes.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    commands
+        .spawn((
+            ShortCircuitFlash {
+                timer: Timer::from_seconds(SHORT_CIRCUIT_SECONDS, TimerMode::Once),
+            },
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::End,
+                padding: UiRect::bottom(Val::Px(40.0)),
+                ..default()
+            },
+            BackgroundColor(SHORT_CIRCUIT_COLOR),
+            Pickable::IGNORE,
+        ))
+        .with_child((
+            Text::new(
+                "Kurzschluss! Die Pole der Batterie sind ohne Verbraucher direkt verbunden.
+Dadurch fliesst ein sehr grosser Strom: Kabel und Batterie werden heiss und koennen brennen.",
+            ),
+            TextFont {
+                font_size: 26.0,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+            TextLayout::new_with_justify(Justify::Center),
+            Pickable::IGNORE,
+        ));
+
+    // Audio
+    commands.spawn((AudioPlayer::new(sounds.spark.clone()), {
+        let mut settings = PlaybackSettings::DESPAWN;
+        settings.volume = Volume::Linear(0.5);
+        settings
+    }));
+}
+
+/// Fades the red overlay out quickly and removes the explanation once the timer runs out.
+fn fade_short_circuit_flash(
+    mut commands: Commands,
+    mut flashes: Query<(Entity, &mut ShortCircuitFlash, &mut BackgroundColor)>,
+    time: Res<Time>,
+) {
+    for (entity, mut flash, mut background) in flashes.iter_mut() {
+        flash.timer.tick(time.delta());
+        if flash.timer.is_finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        let fade = (1.0 - flash.timer.elapsed_secs()).max(0.0);
+        background.0 = SHORT_CIRCUIT_COLOR.with_alpha(SHORT_CIRCUIT_COLOR.alpha() * fade);
+    }
+}
+
+const MAX_LAMP_BRIGHTNESS: f32 = 2.0;
+
+/// HDR tint of a lit lamp, a lamp on its own battery (brightness `1.0`) gets `srgb(5.0, 5.0, 0.0)`.
+fn lamp_color(brightness: f32) -> Color {
+    let glow = 1.0 + 4.0 * brightness.min(MAX_LAMP_BRIGHTNESS);
+    Color::srgb(glow, glow, 0.0)
+}
+
+fn undo_listener(
+    input: Res<ButtonInput<KeyCode>>,
+    mut history: ResMut<MoveHistory>,
+    mut commands: Commands,
+) {
+    let ctrl = input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let shift = input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    if ctrl && input.just_pressed(KeyCode::KeyZ) {
+        match shift {
+            true => history.redo(&mut commands),
+            false => history.undo(&mut commands),
+        }
+    }
+}
+
+/// Arrow keys and WASD slide the tile next to the empty cell into it, in the direction of the key.
+fn slide_listener(
+    input: Res<ButtonInput<KeyCode>>,
+    grid: Res<Grid>,
+    mut commands: Commands,
+    sounds: Res<Sounds>,
+) {
+    // The tile comes from the side opposite to the direction it moves in
+    let from_side = if input.any_just_pressed([KeyCode::ArrowLeft, KeyCode::KeyA]) {
+        Side::Right
+    } else if input.any_just_pressed([KeyCode::ArrowRight, KeyCode::KeyD]) {
+        Side::Left
+    } else if input.any_just_pressed([KeyCode::ArrowUp, KeyCode::KeyW]) {
+        Side::Bottom
+    } else if input.any_just_pressed([KeyCode::ArrowDown, KeyCode::KeyS]) {
+        Side::Top
+    } else {
+        return;
+    };
+
+    let Some(to) = grid.empty_cell() else {
+        return;
+    };
+    let from = (
+        to.0.wrapping_add_signed(from_side.x_offset() as isize),
+        to.1.wrapping_add_signed(from_side.y_offset() as isize),
+    );
+
+    let mv = Move::Slide { from, to };
+
+    if grid.is_legal_move(mv) {
+        commands.trigger(MoveTileRequest {
+            mv,
+            kind: MoveKind::Play,
+        });
+    } else {
+        // Audio
+        commands.spawn((AudioPlayer::new(sounds.misdrop.clone()), {
+            let mut settings = PlaybackSettings::DESPAWN;
+            settings.volume = Volume::Linear(0.2);
+            settings
+        }));
+    }
 }
 
 fn restart_listener(input: Res<ButtonInput<KeyCode>>, mut commands: Commands) {
@@ -1166,123 +2280,120 @@ fn restart_listener(input: Res<ButtonInput<KeyCode>>, mut commands: Commands) {
     }
 }
 
+/// A tile released less than this many pixels from where it was picked up counts as clicked.
+const CLICK_DISTANCE: f32 = 5.0;
+
 struct TileDragSystemCurrent {
     entity: Entity,
     offset_from_cursor: Vec2,
-    start_pos: Vec3,
+    /// Where the tile was picked up, to tell a click from a drag.
+    start_pos: Vec2,
 }
-#[derive(Default, Component)]
+/// Kept for the whole app, so the cursor position survives a restart. The dragged tile is let go
+/// whenever its board is cleared away.
+#[derive(Default, Resource)]
 struct TileDragSystemState {
-    cursor_world_pos: Vec2,
+    /// Last position of the mouse cursor or finger in viewport coordinates.
+    cursor_viewport_pos: Vec2,
     current: Option<TileDragSystemCurrent>,
 }
 
 #[allow(clippy::too_many_arguments)]
 fn tile_drag_system(
-    camera: Single<(Entity, &Tonemapping, Option<&mut Bloom>), With<Camera>>,
-    mut state: Local<TileDragSystemState>,
+    mut state: ResMut<TileDragSystemState>,
     mut cursor_moved_event_reader: MessageReader<CursorMoved>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
-    mut tiles: Query<(Entity, &mut TileComponent, &mut Sprite)>,
+    touches: Res<Touches>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    tiles: Query<(Entity, &TileComponent, &Sprite)>,
     mut transforms: Query<&mut Transform>,
-    mut grid: ResMut<Grid>,
-    asset_server: Res<AssetServer>,
+    grid: Res<Grid>,
+    layout: Res<BoardLayout>,
     mut commands: Commands,
     sounds: Res<Sounds>,
 ) {
-    let (camera_entity, _, _bloom) = camera.into_inner();
-
     // Update cursor position
-    let half_window = Vec2::new(WIN_WIDTH as f32 / 2.0, WIN_HEIGHT as f32 / 2.0);
     if let Some(cursor_event) = cursor_moved_event_reader.read().last() {
-        state.cursor_world_pos = cursor_event.position - half_window;
-        state.cursor_world_pos.y *= -1.0;
+        state.cursor_viewport_pos = cursor_event.position;
     };
 
+    // The first finger on a touch screen drags just like the mouse
+    if let Some(touch) = touches.iter().chain(touches.iter_just_released()).next() {
+        state.cursor_viewport_pos = touch.position();
+    }
+
+    // Through the camera, so it stays right wherever the camera looks and however it zooms
+    let (camera, camera_transform) = camera.into_inner();
+    let Ok(cursor_world_pos) =
+        camera.viewport_to_world_2d(camera_transform, state.cursor_viewport_pos)
+    else {
+        return;
+    };
+
+    let pressed = mouse_button_input.pressed(MouseButton::Left) || touches.iter().next().is_some();
+    let just_pressed =
+        mouse_button_input.just_pressed(MouseButton::Left) || touches.any_just_pressed();
+    let just_released =
+        mouse_button_input.just_released(MouseButton::Left) || touches.any_just_released();
+
     // Drop
-    if mouse_button_input.just_released(MouseButton::Left)
-        && let Some(current) = &state.current
-    {
-        let mut sprite_pos = transforms.get_mut(current.entity).unwrap();
-        let (start_x, start_y) = grid.xy_from_world_pos(current.start_pos.xy());
+    if just_released && let Some(current) = &state.current {
+        // The tile may have been moved by a key, the gamepad or undo while it was held
+        let Ok((_, tile, _)) = tiles.get(current.entity) else {
+            state.current = None;
+            return;
+        };
+        let (start_x, start_y) = (tile.x, tile.y);
+        let Ok(mut sprite_pos) = transforms.get_mut(current.entity) else {
+            state.current = None;
+            return;
+        };
+        let cell_pos = layout
+            .world_pos_from_xy(start_x, start_y)
+            .unwrap()
+            .extend(0.0);
 
         // Checks
-        let (new_x, new_y) = grid.xy_from_world_pos(sprite_pos.translation.xy());
-        let is_new_cell_empty = grid.get(new_x, new_y).unwrap().is_none();
-        let has_unobstructed_path = grid.has_unobstructed_path((new_x, new_y), (start_x, start_y));
-
-        if is_new_cell_empty && has_unobstructed_path {
+        let (new_x, new_y) = layout.xy_from_world_pos(sprite_pos.translation.xy());
+        let mv = Move::Slide {
+            from: (start_x, start_y),
+            to: (new_x, new_y),
+        };
+        let clicked = sprite_pos.translation.xy().distance(current.start_pos) < CLICK_DISTANCE;
+        let toggle = Move::Toggle {
+            cell: (start_x, start_y),
+        };
+        // Tapping a tile slides it into the empty cell
+        let tap = grid.empty_cell().map(|to| Move::Slide {
+            from: (start_x, start_y),
+            to,
+        });
+
+        if clicked && grid.is_legal_move(toggle) {
+            sprite_pos.translation = cell_pos;
+
+            commands.trigger(MoveTileRequest {
+                mv: toggle,
+                kind: MoveKind::Play,
+            });
+        } else if let Some(tap) = tap.filter(|&tap| clicked && grid.is_legal_move(tap)) {
+            sprite_pos.translation = cell_pos;
+
+            commands.trigger(MoveTileRequest {
+                mv: tap,
+                kind: MoveKind::Play,
+            });
+        } else if grid.is_legal_move(mv) {
             // Snap
-            let snapped = grid.snap_to_grid(sprite_pos.translation.xy());
+            let snapped = layout.snap_to_grid(sprite_pos.translation.xy());
             sprite_pos.translation = Vec3::new(snapped.x, snapped.y, 0.0);
 
-            // Update Grid
-            grid.swap((new_x, new_y), (start_x, start_y));
-
-            let mut tile = tiles.get_mut(current.entity).unwrap().1;
-            tile.x = new_x;
-            tile.y = new_y;
-
-            // Solved?
-            let is_solved = grid.is_solved();
-
-            for (_, tile, mut sprite) in tiles.iter_mut() {
-                if let Some(lamp @ Tile::Lamp { .. }) = grid.get(tile.x, tile.y).unwrap() {
-                    match is_solved {
-                        true => {
-                            sprite.image =
-                                asset_server.load(get_path_to_lamp_on_sprite_for_tile(lamp));
-                            sprite.color = Color::srgb(5.0, 5.0, 0.0);
-                            continue;
-                        }
-                        false => {
-                            sprite.image =
-                                asset_server.load(get_path_to_start_sprite_for_tile(lamp));
-                            sprite.color = Color::WHITE;
-                            continue;
-                        }
-                    }
-                }
-            }
-
-            if is_solved {
-                // Bloom
-                commands.entity(camera_entity).remove::<Bloom>();
-                commands.entity(camera_entity).insert((
-                    Bloom {
-                        composite_mode: BloomCompositeMode::Additive,
-                        intensity: 0.1,
-                        low_frequency_boost_curvature: 0.9,
-                        low_frequency_boost: 0.75,
-                        scale: Vec2::splat(2.06),
-                        prefilter: BloomPrefilter {
-                            threshold: 0.6,
-                            threshold_softness: 0.0,
-                        },
-                        ..default()
-                    },
-                    Tonemapping::TonyMcMapface,
-                ));
-
-                // Audio
-                commands.spawn((
-                    AudioPlayer::new(sounds.lamp_turns_on.clone()),
-                    PlaybackSettings::DESPAWN,
-                ));
-            } else {
-                // Bloom
-                commands.entity(camera_entity).remove::<Bloom>();
-                commands.entity(camera_entity).insert(Tonemapping::None);
-            }
-
-            // Audio
-            commands.spawn((AudioPlayer::new(sounds.drop.clone()), {
-                let mut settings = PlaybackSettings::DESPAWN;
-                settings.volume = Volume::Linear(0.15);
-                settings
-            }));
+            commands.trigger(MoveTileRequest {
+                mv,
+                kind: MoveKind::Play,
+            });
         } else {
-            sprite_pos.translation = current.start_pos.truncate().extend(0.0);
+            sprite_pos.translation = cell_pos;
 
             // Audio
             commands.spawn((AudioPlayer::new(sounds.misdrop.clone()), {
@@ -1297,26 +2408,27 @@ fn tile_drag_system(
     }
 
     // Drag
-    if mouse_button_input.pressed(MouseButton::Left)
-        && let Some(current) = &state.current
-    {
-        let mut sprite_pos = transforms.get_mut(current.entity).unwrap();
-
-        sprite_pos.translation.x = state.cursor_world_pos.x + current.offset_from_cursor.x;
-        sprite_pos.translation.y = state.cursor_world_pos.y + current.offset_from_cursor.y;
+    if pressed && let Some(current) = &state.current {
+        let Ok(mut sprite_pos) = transforms.get_mut(current.entity) else {
+            state.current = None;
+            return;
+        };
+
+        sprite_pos.translation.x = cursor_world_pos.x + current.offset_from_cursor.x;
+        sprite_pos.translation.y = cursor_world_pos.y + current.offset_from_cursor.y;
         sprite_pos.translation.z = 10.0;
     }
 
     // Start drag
-    if mouse_button_input.just_pressed(MouseButton::Left) {
-        for (entity, _, sprite) in tiles.iter_mut() {
+    if just_pressed {
+        for (entity, _, sprite) in tiles.iter() {
             let sprite_pos = transforms
                 .get_mut(entity)
                 .unwrap()
                 .translation
                 .truncate()
                 .extend(10.0);
-            let cursor_pos = state.cursor_world_pos;
+            let cursor_pos = cursor_world_pos;
 
             let sprite_size = sprite.custom_size.unwrap();
 
@@ -1325,13 +2437,14 @@ fn tile_drag_system(
                 && cursor_pos.y <= sprite_pos.y
                 && cursor_pos.y >= sprite_pos.y - sprite_size.y
             {
+                commands.entity(entity).remove::<Sliding>();
                 state.current = Some(TileDragSystemCurrent {
                     entity,
                     offset_from_cursor: Vec2::new(
                         sprite_pos.x - cursor_pos.x,
                         sprite_pos.y - cursor_pos.y,
                     ),
-                    start_pos: sprite_pos,
+                    start_pos: sprite_pos.xy(),
                 });
 
                 // Audio
@@ -1344,3 +2457,48 @@ fn tile_drag_system(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::tests::shipped_levels;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use std::collections::{HashSet, VecDeque};
+
+    /// Every board that legal moves lead to from `start`.
+    fn reachable(start: &Grid) -> HashSet<Grid> {
+        let mut seen = HashSet::from([start.clone()]);
+        let mut queue = VecDeque::from([start.clone()]);
+        while let Some(board) = queue.pop_front() {
+            for mv in board.legal_moves() {
+                let mut next = board.clone();
+                next.apply(mv);
+                if seen.insert(next.clone()) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        seen
+    }
+
+    #[test]
+    fn scrambled_levels_are_unsolved_and_reachable() {
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for (file, level) in shipped_levels() {
+            let solved = Grid::new(&level);
+            let boards = reachable(&solved);
+
+            for _ in 0..50 {
+                let mut grid = solved.clone();
+                grid.scramble(&mut rng);
+                assert!(
+                    !grid.is_solved(),
+                    "{file} was scrambled into a solved board"
+                );
+                assert!(boards.contains(&grid), "{file} was scrambled out of reach");
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{Grid, Move, Tile};
+use std::collections::HashSet;
+
+/// Gives up on boards with more reachable states than this instead of freezing the game.
+const MAX_EXPLORED_STATES: usize = 2_000_000;
+
+/// Shortest move sequence from a board to a solved board, as found by [`solve`].
+#[derive(Clone, Debug)]
+pub struct Solution {
+    pub moves: Vec<Move>,
+    /// Number of boards that were checked before the solution was found.
+    pub explored: usize,
+}
+
+/// A board's tiles in row-major order, as indices into a [`Palette`]. Much smaller than a whole
+/// `Grid`, which also carries the goal.
+type Key = Box<[u16]>;
+
+/// Every distinct tile (including the empty cell) the search has come across.
+#[derive(Default)]
+struct Palette(Vec<Option<Tile>>);
+
+impl Palette {
+    fn key(&mut self, grid: &Grid) -> Key {
+        grid.tiles
+            .iter()
+            .map(|tile| match self.0.iter().position(|known| known == tile) {
+                Some(index) => index as u16,
+                None => {
+                    self.0.push(*tile);
+                    (self.0.len() - 1) as u16
+                }
+            })
+            .collect()
+    }
+
+    /// Lays the tiles of `key` out on `grid`, which keeps its goal.
+    fn write(&self, key: &Key, grid: &mut Grid) {
+        for (tile, &index) in grid.tiles.iter_mut().zip(key.iter()) {
+            *tile = self.0[index as usize];
+        }
+    }
+}
+
+/// Breadth-first search over all legal moves, starting at `grid`, for the closest board where
+/// [`Grid::is_solved`] holds. Returns `None` if no solved board is reachable within
+/// `MAX_EXPLORED_STATES` boards.
+pub fn solve(grid: &Grid) -> Option<Solution> {
+    let mut palette = Palette::default();
+    // One board that every visited state is laid out on in turn, so the goal is never copied
+    let mut board = grid.clone();
+
+    // Every visited board in the order it was found, which is the order of the search, with the
+    // board and move it was reached from
+    let start = palette.key(&board);
+    let mut visited: Vec<(Key, Option<(usize, Move)>)> = vec![(start.clone(), None)];
+    let mut seen = HashSet::from([start]);
+
+    let mut index = 0;
+    while index < visited.len() {
+        palette.write(&visited[index].0, &mut board);
+        let explored = index + 1;
+
+        if board.is_solved() {
+            let mut moves = Vec::new();
+            let mut current = index;
+            while let Some((parent, mv)) = visited[current].1 {
+                moves.push(mv);
+                current = parent;
+            }
+            moves.reverse();
+
+            return Some(Solution { moves, explored });
+        }
+
+        if explored >= MAX_EXPLORED_STATES {
+            return None;
+        }
+
+        for mv in board.legal_moves() {
+            board.apply(mv);
+            let key = palette.key(&board);
+            board.apply(mv.reversed());
+
+            if seen.insert(key.clone()) {
+                visited.push((key, Some((index, mv))));
+            }
+        }
+
+        index += 1;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::Goal;
+    use crate::level::tests::shipped_levels;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    /// The easy level in its solved layout.
+    fn easy() -> Grid {
+        let tiles: Vec<Option<Tile>> = ron::from_str(
+            "[
+                Some(Cable(entry: Right, exit: Bottom)),
+                Some(Battery(plus_side: Left, minus_side: Right)),
+                Some(N),
+                Some(Cable(entry: Top, exit: Right)),
+                Some(Lamp(entry: Left, exit: Bottom)),
+                Some(P),
+                None,
+                Some(Cable(entry: Top, exit: Right)),
+                Some(Cable(entry: Left, exit: Top)),
+            ]",
+        )
+        .unwrap();
+        Grid {
+            tiles: grid::Grid::from_vec(tiles, 3),
+            goal: Goal::AllLamps,
+        }
+    }
+
+    fn moved(grid: &Grid, mv: Move) -> Grid {
+        assert!(grid.is_legal_move(mv), "{mv:?} is not legal");
+        let mut grid = grid.clone();
+        grid.apply(mv);
+        grid
+    }
+
+    #[test]
+    fn solved_board_needs_no_moves() {
+        let solution = solve(&easy()).unwrap();
+        assert!(solution.moves.is_empty());
+        assert_eq!(solution.explored, 1);
+    }
+
+    #[test]
+    fn solution_solves_scrambled_levels() {
+        let mut rng = StdRng::seed_from_u64(3);
+
+        for (file, level) in shipped_levels() {
+            let mut grid = Grid::new(&level);
+            grid.scramble(&mut rng);
+
+            let solution = solve(&grid).unwrap_or_else(|| panic!("{file} has no solution"));
+            for mv in solution.moves {
+                grid = moved(&grid, mv);
+            }
+            assert!(grid.is_solved(), "the solution of {file} does not solve it");
+        }
+    }
+
+    #[test]
+    fn solution_is_shortest() {
+        // Sliding the cable under the lamp away breaks the loop
+        let one_away = moved(
+            &easy(),
+            Move::Slide {
+                from: (1, 2),
+                to: (0, 2),
+            },
+        );
+        assert!(!one_away.is_solved());
+        assert_eq!(solve(&one_away).unwrap().moves.len(), 1);
+
+        let two_away = moved(
+            &one_away,
+            Move::Slide {
+                from: (2, 2),
+                to: (1, 2),
+            },
+        );
+        assert!(!two_away.is_solved());
+        for mv in two_away.legal_moves() {
+            assert!(!moved(&two_away, mv).is_solved(), "{mv:?} solves it");
+        }
+        assert_eq!(solve(&two_away).unwrap().moves.len(), 2);
+    }
+}